@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{
+    Read,
+    Write,
+    stderr,
+};
+use std::path::PathBuf;
+
+pub const CONFIG_ENV: &'static str = "SYSHOOKS_CONFIG";
+
+/// Settings read from an ini style file:
+///
+/// ```text
+/// [brightness]
+/// floor = 5%
+///
+/// [brightness.intel_backlight]
+/// floor = 200
+/// ```
+#[derive(Debug, Default)]
+pub struct Config {
+    sections: HashMap<String, HashMap<String, String>>,
+}
+
+impl Config {
+    /// Reads `$SYSHOOKS_CONFIG`, or `$XDG_CONFIG_HOME/syshooks/config`.
+    /// A missing file is an empty config.
+    pub fn load() -> Self {
+        let path = match config_path() {
+            Some(p) => p,
+            None => return Config::default(),
+        };
+        let mut text = String::new();
+        match File::open(&path) {
+            Ok(mut f) => {
+                if let Err(e) = f.read_to_string(&mut text) {
+                    writeln!(stderr(), "Could not read {}: {}", path.display(), e).ok();
+                }
+            },
+            Err(_) => return Config::default(),
+        }
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut section = String::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            match line.find('=') {
+                Some(i) => {
+                    sections.entry(section.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(line[..i].trim().to_string(), line[i + 1..].trim().to_string());
+                },
+                None => {
+                    writeln!(stderr(), "Ignoring config line without '=': {}", line).ok();
+                },
+            }
+        }
+
+        Config {
+            sections: sections,
+        }
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections.get(section)
+            .and_then(|s| s.get(key))
+            .map(|v| v as &str)
+    }
+
+    /// Looks in `[section.name]` first, then in `[section]`.
+    pub fn get_for(&self, section: &str, name: Option<&str>, key: &str) -> Option<&str> {
+        name.and_then(|n| self.get(&format!("{}.{}", section, n), key))
+            .or_else(|| self.get(section, key))
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|d| d.join("syshooks").join("config"))
+}
+
+/// `$var`, or `$HOME/fallback` if it is unset.
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_keys() {
+        let config = Config::parse("
+            # a comment
+            top = level
+            [brightness]
+            floor = 5%
+            ; another comment
+            [ brightness.intel_backlight ]
+            floor=200
+            no equals sign
+            [volume]
+            element = Master = Front
+        ");
+        assert_eq!(config.get("", "top"), Some("level"));
+        assert_eq!(config.get("brightness", "floor"), Some("5%"));
+        assert_eq!(config.get("brightness.intel_backlight", "floor"), Some("200"));
+        assert_eq!(config.get("volume", "element"), Some("Master = Front"));
+        assert_eq!(config.get("brightness", "no equals sign"), None);
+        assert_eq!(config.get("mic", "element"), None);
+    }
+
+    #[test]
+    fn looks_up_the_device_section_before_the_general_one() {
+        let config = Config::parse("[brightness]\nfloor = 5%\nexponent = 2\n\
+                                    [brightness.intel_backlight]\nfloor = 200\n");
+        assert_eq!(config.get_for("brightness", Some("intel_backlight"), "floor"), Some("200"));
+        assert_eq!(config.get_for("brightness", Some("intel_backlight"), "exponent"), Some("2"));
+        assert_eq!(config.get_for("brightness", Some("acpi_video0"), "floor"), Some("5%"));
+        assert_eq!(config.get_for("brightness", None, "floor"), Some("5%"));
+        assert_eq!(config.get_for("volume", Some("intel_backlight"), "floor"), None);
+    }
+}
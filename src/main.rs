@@ -1,5 +1,12 @@
+use std::env;
 use std::env::args;
+use std::path::PathBuf;
 use std::process::exit;
+use std::thread::sleep;
+use std::time::{
+    Duration,
+    SystemTime,
+};
 use std::io::{
     stderr,
     Write,
@@ -8,81 +15,282 @@ use std::io::{
 mod notify;
 mod manage;
 mod audio;
+mod config;
+mod state;
+#[cfg(test)]
+mod testing;
 
 use manage::brightness::Brightness;
+use manage::brightness::fs;
+use manage::brightness::fs::FsBrightness;
+use manage::brightness::fade::{
+    Easing,
+    Fade,
+};
+use manage::brightness::floor::{
+    Floor,
+    Floored,
+    DEFAULT_FLOOR,
+};
+use manage::brightness::perceptual;
+use manage::brightness::perceptual::{
+    Curve,
+    Perceptual,
+};
+use manage::brightness::dbus::{
+    Bus,
+    DbusOptions,
+};
+use manage::brightness::gamma;
+use manage::brightness::gamma::{
+    Gamma,
+    GammaBrightness,
+};
+use manage::brightness::watch::Watcher;
 use manage::brightness::xcb::XcbBrightness;
-use manage::volume::Mixer;
+use manage::brightness::xcb::Event as XcbEvent;
+use manage::brightness::xcb::Error as XcbError;
+use manage::brightness::probe::{
+    AnyBrightness,
+    Backend,
+    BACKEND_ENV,
+};
+use manage::brightness::auto::{
+    AutoBrightness,
+    LuxCurve,
+};
+use manage::color;
+use manage::light;
+use manage::light::LightSensor;
+use manage::sysfs;
+use manage::night::{
+    Schedule,
+    TimeOfDay,
+    Times,
+};
+use manage::night;
+use manage::volume::{
+    Card,
+    Mixer,
+    Scale,
+};
 
 use notify::volume::Volume;
 use notify::volume;
 
 use audio::notifications;
 
+use config::Config;
+use state::State;
+
 fn help() -> ! {
     let name = args().nth(0).unwrap_or_else(|| {
         "pleb_ui".to_string()
     });
     println!(r#"{0} USAGE
-    {0} brightness {{up|down|set}} <percent>
-    {0}            get
+    {0} brightness [<options>] {{up|down|set}} <percent>
+    {0} brightness [<options>] get
+    {0} brightness list
+    {0} brightness [--backend <name>] [--device <name>] {{save|restore}}
+    {0} brightness [<options>] [--interval <ms>] auto
+    {0} brightness [<options>] [--interval <ms>] watch
+
+    {0} kbd-brightness [--device <name>] {{up|down}} [<percent>]
+    {0} kbd-brightness [--device <name>] {{set <percent>|get|toggle}}
+
+    {0} color-temp [--device <output>] set <kelvin>
+    {0} color-temp [--device <output>] reset
+    {0} color-temp [--device <output>] schedule
+
+    {0} volume [<mixer options>] {{up|down|set}} <percent>
+    {0} volume [<mixer options>] {{get|toggle-mute}}
+    {0} volume [<mixer options>] balance <-100..100>
+    {0} volume [<mixer options>] {{save|restore}}
+    {0} volume [--card <name>] [--json] list
+
+    {0} mic [<mixer options>] {{up|down|set}} <percent>
+    {0} mic [<mixer options>] {{get|toggle-mute}}
+
+    {0} {{-h|--help}}
+
+BRIGHTNESS OPTIONS
+    --backend {{xcb|fs|logind|dbus|gamma}}
+                             use this backend instead of probing (also ${2});
+                             logind reads sysfs but writes through systemd-logind,
+                             gamma dims outputs without a backlight in software
+    --device <name>          a RandR output (eDP-1) or sysfs backlight (intel_backlight);
+                             without it every RandR output is changed, dimming those
+                             without a backlight through their gamma ramps
+    --raw                    use the backend's own units, e.g. sysfs steps, instead of
+                             perceptual percentages
+    --linear                 use linear percentages instead of perceptual ones
+    --exponent <e>           gamma of the perceptual scale (default {1})
+    --floor <value>[%]       lowest brightness allowed, in the backend's own units (as
+                             printed by --raw get) or percent
+                             (default 1%, or `floor` in the config file)
+    --allow-off              ignore the floor and allow turning the backlight off
+    --fade <ms>              fade to the new value over <ms> milliseconds
+    --fade-steps <n>         number of steps in a fade
+    --easing {{linear|ease-in|ease-out|ease-in-out}}
+
+MIXER OPTIONS
+    --card <name>            ALSA card to use, e.g. hw:1 (default: default)
+    --element <name>         simple mixer element; without it Master, PCM, Speaker
+                             and then the first element with a playback volume
+                             are tried, or for mic Capture, Mic, Internal Mic and
+                             the first with a capture volume
+    --index <n>              index of the element (default 0)
+    --db                     step the volume evenly in loudness using the element's
+                             dB range, as alsamixer does (playback only)
+
+CONFIGURATION
+    Defaults are read from ${3} or $XDG_CONFIG_HOME/syshooks/config:
+
+        [brightness]
+        backend = fs
+        floor = 5%
 
-    {0} volume {{up|down|set}} <percent>
-    {0} volume toggle-mute
+        [brightness.intel_backlight]
+        floor = 200
 
-    {0} {{-h|--help}}"#, name);
+        [auto-brightness]
+        sensor = iio:device0
+        curve = 0:5, 10:20, 100:40, 1000:75, 10000:100
+        smoothing = 0.3
+        hysteresis = 3
+        interval = 1000
+
+        [volume]
+        card = hw:1
+        element = PCM
+        scale = db
+
+        [mic]
+        element = Capture
+
+        [sysfs]
+        # where sysfs is mounted, unless ${4} is set
+        root = /sys
+
+        [dbus]
+        # session, system or an address such as unix:path=/tmp/bus
+        bus = session
+        timeout = 2000
+        # where systemd-logind is, for backlights sysfs won't let us write
+        logind-bus = system
+
+        [night-light]
+        day = 6500
+        night = 3500
+        dawn = 07:00
+        dusk = 20:00
+        # or follow the sun instead of dawn and dusk
+        latitude = 51.5
+        longitude = -0.1
+        transition = 30
+        interval = 60"#,
+             name, perceptual::DEFAULT_EXPONENT, BACKEND_ENV, config::CONFIG_ENV,
+             sysfs::ROOT_ENV);
     exit(255);
 }
 
+/// Removes `flag` from `args` and returns whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Removes `flag <value>` from `args` and returns the value.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = match args.iter().position(|a| a == flag) {
+        Some(p) => p,
+        None => return None,
+    };
+    if pos + 1 >= args.len() {
+        help();
+    }
+    args.remove(pos);
+    Some(args.remove(pos))
+}
+
 fn main() {
+    let config = Config::load();
+
     match args().nth(1).as_ref().map(|s| s as &str) {
-        Some("volume") => set_volume(),
-        Some("brightness") => set_brightness(),
+        Some("volume") => set_volume(&config),
+        Some("brightness") => set_brightness(&config),
+        Some("kbd-brightness") => set_kbd_brightness(&config),
+        Some("mic") => set_mic(&config),
+        Some("color-temp") => set_color_temp(&config),
         _ => help(),
     };
 }
 
-fn set_volume() {
-    let mut args = args().skip(2);
+/// Where sysfs is mounted, from the environment or the `[sysfs]` config
+/// section.
+fn sysfs_root(config: &Config) -> PathBuf {
+    sysfs::root(config.get("sysfs", "root"))
+}
 
-    let (mult, set, toggle_mute) = if let Some(arg) = args.next() {
-        if arg == "up" {
-            (1.0, false, false)
-        } else if arg == "down" {
-            (-1.0, false, false)
-        } else if arg == "set" {
-            (1.0, true, false)
-        } else if arg == "toggle-mute" {
-            (1.0, false, true)
-        } else {
+fn set_volume(config: &Config) {
+    let mut args: Vec<String> = args().skip(2).collect();
+
+    // `list` covers every card unless one is named on the command line.
+    let json = take_flag(&mut args, "--json");
+    let named_card = args.iter().position(|a| a == "--card")
+        .and_then(|pos| args.get(pos + 1))
+        .cloned();
+    let options = MixerOptions::take(&mut args, config, "volume");
+    if args.first().map(|a| a == "list").unwrap_or(false) {
+        if args.len() > 1 {
             help();
         }
-    } else {
+        list_volume(named_card, json);
+        return;
+    }
+    if json {
         help();
-    };
+    }
+    let (command, value) = mixer_command(args, &["up", "down", "set", "balance"],
+                                         &["get", "toggle-mute", "save", "restore"]);
+    let mut master = options.open(false);
+    let balance_key = format!("{}.balance", volume_key(&master));
+    if let Some(balance) = State::load().ok()
+        .and_then(|state| state.get(&balance_key).and_then(|b| b.parse().ok()))
+    {
+        master.keep_balance(balance);
+    }
 
-    let master = match Mixer::new("default", "Master") {
-        Ok(m) => m,
-        Err(e) => {
-            writeln!(stderr(), "There was an error opening the alsa mixer: {:?}", e).ok();
-            exit(1)
+    match (&command as &str, value) {
+        ("up", Some(p)) => master.change_volume_clip(p / 100.0),
+        ("down", Some(p)) => master.change_volume_clip(-p / 100.0),
+        ("set", Some(p)) => master.set_volume(p / 100.0),
+        ("toggle-mute", None) => {
+            if master.toggle_mute().is_err() {
+                writeln!(stderr(), "This mixer cannot be muted / unmuted!").ok();
+            }
         },
-    };
-
-    if toggle_mute {
-        if master.toggle_mute().is_err() {
-            writeln!(stderr(), "This mixer cannot be muted / unmuted!").ok();
-        }
-    } else {
-        let percent: f32 = args.next()
-            .and_then(|p| p.parse().ok())
-            .unwrap_or_else(|| {
-                help()
-            });
-        if set {
-            master.set_volume(percent / 100.0);
-        } else {
-            master.change_volume_clip(percent * mult / 100.0);
-        }
+        ("balance", Some(b)) => {
+            master.set_balance(b);
+            let mut state = load_state();
+            state.set(balance_key, master.balance());
+            save_state(&state);
+            return;
+        },
+        ("save", None) | ("restore", None) => {
+            persist_volume(&mut master, command == "save");
+            return;
+        },
+        ("get", None) => {
+            print_volume(&master);
+            return;
+        },
+        _ => help(),
     }
 
     let vol_status = if master.is_muted() {
@@ -99,20 +307,523 @@ fn set_volume() {
     }
 }
 
-fn set_brightness() {
-    let mut args = args().skip(2);
+/// Prints `channel percent` for each channel, then the balance of stereo
+/// elements.
+fn print_volume(master: &Mixer) {
+    let muted = if master.is_muted() { "\tmuted" } else { "" };
+    for channel in master.channels() {
+        println!("{}\t{}{}", channel.name, (master.volume_of(channel.id) * 100.0).round(), muted);
+    }
+    if !master.is_mono() {
+        println!("balance\t{}", master.balance().round());
+    }
+}
+
+fn set_mic(config: &Config) {
+    let mut args: Vec<String> = args().skip(2).collect();
+
+    let options = MixerOptions::take(&mut args, config, "mic");
+    let (command, percent) = mixer_command(args, &["up", "down", "set"], &["get", "toggle-mute"]);
+    let mic = options.open(true);
+
+    match (&command as &str, percent) {
+        ("up", Some(p)) => mic.change_capture_volume_clip(p / 100.0),
+        ("down", Some(p)) => mic.change_capture_volume_clip(-p / 100.0),
+        ("set", Some(p)) => mic.set_capture_volume(p / 100.0),
+        ("toggle-mute", None) => {
+            if mic.toggle_capture_mute().is_err() {
+                writeln!(stderr(), "This microphone cannot be muted / unmuted!").ok();
+            }
+        },
+        ("get", None) => {
+            let percent = (mic.capture_volume() * 100.0).round();
+            if mic.is_capture_muted() {
+                println!("{} muted", percent);
+            } else {
+                println!("{}", percent);
+            }
+            return;
+        },
+        _ => help(),
+    }
+
+    let mic_status = if mic.is_capture_muted() {
+        Volume::Muted
+    } else {
+        Volume::Percent((mic.capture_volume() * 100.0).round() as u32)
+    };
+
+    if let Err(e) = volume::show_mic(mic_status) {
+        writeln!(stderr(), "Error showing microphone notification: {}", e).ok();
+    }
+}
+
+/// Prints the mixer elements of `card`, or of every card, with what each
+/// can do, one tab separated line per element and direction.
+fn list_volume(card: Option<String>, json: bool) {
+    let cards = match card {
+        Some(id) => manage::volume::elements(&id).map(|elements| vec![Card {
+            id: id,
+            name: String::new(),
+            elements: elements,
+        }]),
+        None => manage::volume::cards(),
+    };
+    let cards = match cards {
+        Ok(c) => c,
+        Err(e) => {
+            writeln!(stderr(), "Could not list sound cards: {:?}", e).ok();
+            exit(1)
+        },
+    };
+
+    if json {
+        let cards: Vec<String> = cards.iter().map(Card::to_json).collect();
+        println!("[{}]", cards.join(","));
+        return;
+    }
+
+    for card in cards {
+        println!("{}\t{}", card.id, card.name);
+        for element in card.elements {
+            let directions = [("playback", &element.playback), ("capture", &element.capture)];
+            for &(direction, controls) in directions.iter() {
+                let controls = match *controls {
+                    Some(ref c) => c,
+                    None => continue,
+                };
+                println!("\t{},{}\t{}\t{}\t{}\t{}\t{}",
+                         element.name, element.index, direction,
+                         controls.volume.map(|(min, max)| format!("{}..{}", min, max))
+                             .unwrap_or("-".to_string()),
+                         controls.db.map(|(min, max)| format!("{:.2}..{:.2}dB", min, max))
+                             .unwrap_or("-".to_string()),
+                         if controls.switch { "switch" } else { "-" },
+                         controls.channels.join(","));
+            }
+        }
+    }
+}
+
+/// The mixer element picked by `--card`, `--element` and `--index`,
+/// falling back to a section of the config file and then to the playback
+/// or capture defaults of `Mixer`.
+struct MixerOptions {
+    card: String,
+    element: Option<String>,
+    index: u32,
+    db: bool,
+}
+
+impl MixerOptions {
+    /// Removes the mixer options from `args`, filling in from `section`.
+    fn take(args: &mut Vec<String>, config: &Config, section: &str) -> Self {
+        let card = take_option(args, "--card")
+            .or_else(|| config.get(section, "card").map(|c| c.to_string()))
+            .unwrap_or_else(|| "default".to_string());
+        let element = take_option(args, "--element")
+            .or_else(|| config.get(section, "element").map(|e| e.to_string()));
+        let index = take_option(args, "--index")
+            .or_else(|| config.get(section, "index").map(|i| i.to_string()))
+            .map(|i| i.parse::<u32>().unwrap_or_else(|_| help()))
+            .unwrap_or(0);
+        let db = take_flag(args, "--db") || config.get(section, "scale") == Some("db");
+
+        MixerOptions {
+            card: card,
+            element: element,
+            index: index,
+            db: db,
+        }
+    }
+
+    /// Opens the element, for playback or `capture`.
+    fn open(&self, capture: bool) -> Mixer {
+        let element = self.element.as_ref().map(|e| e as &str);
+        let mixer = if capture {
+            Mixer::find_capture(&self.card, element, self.index)
+        } else {
+            Mixer::find(&self.card, element, self.index)
+        };
+        let mut mixer = match mixer {
+            Ok(m) => m,
+            Err(e) => {
+                writeln!(stderr(), "There was an error opening the alsa mixer: {:?}", e).ok();
+                exit(1)
+            },
+        };
+        if self.db && !capture {
+            mixer.set_scale(Scale::Db);
+        }
+        mixer
+    }
+}
+
+/// Reads a command and its percentage from `args`, showing the usage
+/// unless it is one of `with_value` followed by a number or one of
+/// `without_value` on its own. Done before opening the mixer, so a typo
+/// gets the usage rather than a mixer error.
+fn mixer_command(args: Vec<String>, with_value: &[&str], without_value: &[&str])
+    -> (String, Option<f32>)
+{
+    let mut args = args.into_iter();
+    let command = args.next().unwrap_or_else(|| help());
+    let value = args.next();
+
+    match value {
+        Some(v) if with_value.contains(&(&command as &str)) => {
+            (command, Some(v.parse().unwrap_or_else(|_| help())))
+        },
+        None if without_value.contains(&(&command as &str)) => (command, None),
+        _ => help(),
+    }
+}
+
+fn load_state() -> State {
+    match State::load() {
+        Ok(s) => s,
+        Err(e) => {
+            writeln!(stderr(), "Could not read saved state: {}", e).ok();
+            exit(5)
+        },
+    }
+}
+
+fn save_state(state: &State) {
+    if let Err(e) = state.save() {
+        writeln!(stderr(), "Could not save state: {}", e).ok();
+        exit(5)
+    }
+}
+
+/// The state file key of the mixer's element, by card, name and index.
+fn volume_key(master: &Mixer) -> String {
+    match master.index() {
+        0 => format!("volume.{}.{}", master.card(), master.name()),
+        index => format!("volume.{}.{},{}", master.card(), master.name(), index),
+    }
+}
+
+/// Saves or restores the volume, balance and mute state of the mixer,
+/// keyed by card and element. Levels are kept as fractions of the raw
+/// volume range whatever scale the mixer was opened with, so a restore
+/// rounds back to the step that was saved.
+fn persist_volume(master: &mut Mixer, save: bool) {
+    master.set_scale(Scale::Raw);
+    let mut state = load_state();
+    let key = volume_key(master);
+
+    if save {
+        state.set(key.clone(), master.volume());
+        state.set(format!("{}.balance", key), master.balance());
+        state.set(format!("{}.muted", key), master.is_muted());
+        save_state(&state);
+        return;
+    }
+
+    if let Some(balance) = state.get(&format!("{}.balance", key)).and_then(|b| b.parse().ok()) {
+        master.keep_balance(balance);
+    }
+    if let Some(volume) = state.get(&key).and_then(|v| v.parse().ok()) {
+        master.set_volume(volume);
+    }
+    match state.get(&format!("{}.muted", key)) {
+        Some("true") => master.mute().ok(),
+        Some("false") => master.unmute().ok(),
+        _ => None,
+    };
+}
+
+/// Saves or restores the brightness of every device `bright_control`
+/// manages, keyed by backend and device name.
+fn persist_brightness(bright_control: &AnyBrightness, save: bool) {
+    let mut state = load_state();
+
+    if let AnyBrightness::Xcb(ref xcb) = *bright_control {
+        for display in xcb.displays() {
+            let key = format!("brightness.xcb.{}", display.name());
+            if save {
+                match xcb.current_of(display) {
+                    Ok(c) => state.set(key, c),
+                    Err(e) => {
+                        writeln!(stderr(), "Could not get brightness of {}: {}",
+                                 display.name(), e).ok();
+                    },
+                }
+            } else if let Some(value) = state.get(&key).and_then(|v| v.parse().ok()) {
+                if let Err(e) = xcb.set_of(display, value) {
+                    writeln!(stderr(), "Could not restore brightness of {}: {}",
+                             display.name(), e).ok();
+                }
+            }
+        }
+    } else {
+        let key = format!("brightness.{}.{}", bright_control.backend().name(),
+                          bright_control.device().unwrap_or("default"));
+        if save {
+            match bright_control.current() {
+                Ok(c) => state.set(key, c),
+                Err(e) => {
+                    writeln!(stderr(), "Could not get brightness: {}", e).ok();
+                    exit(3)
+                },
+            }
+        } else if let Some(value) = state.get(&key).and_then(|v| v.parse().ok()) {
+            if let Err(e) = bright_control.set(value) {
+                writeln!(stderr(), "Could not restore brightness: {}", e).ok();
+            }
+        }
+    }
+
+    if save {
+        save_state(&state);
+    }
+}
+
+/// Where the dbus backend and logind are, from the `[dbus]` config section.
+fn dbus_options(config: &Config) -> DbusOptions {
+    let section = "dbus";
+
+    let mut options = DbusOptions::default()
+        .destination(config.get(section, "destination").map(|d| d.to_string()))
+        .path(config.get(section, "path").map(|p| p.to_string()))
+        .interface(config.get(section, "interface").map(|i| i.to_string()));
+    if let Some(bus) = config.get(section, "bus") {
+        options = options.bus(bus.parse::<Bus>().unwrap_or_else(|_| {
+            writeln!(stderr(), "Invalid D-Bus bus: {}", bus).ok();
+            exit(2)
+        }));
+    }
+    if let Some(bus) = config.get(section, "logind-bus") {
+        options = options.logind(bus.parse::<Bus>().unwrap_or_else(|_| {
+            writeln!(stderr(), "Invalid D-Bus bus for logind: {}", bus).ok();
+            exit(2)
+        }));
+    }
+    if let Some(timeout) = config.get(section, "timeout").and_then(|t| t.parse().ok()) {
+        options = options.timeout(timeout);
+    }
+    options
+}
+
+fn set_brightness(config: &Config) {
+    let mut args: Vec<String> = args().skip(2).collect();
+
+    let forced = take_option(&mut args, "--backend")
+        .or_else(|| env::var(BACKEND_ENV).ok())
+        .or_else(|| config.get("brightness", "backend").map(|b| b.to_string()))
+        .map(|b| match b.parse::<Backend>() {
+            Ok(backend) => backend,
+            Err(e) => {
+                writeln!(stderr(), "{}", e).ok();
+                exit(2)
+            },
+        });
+
+    let device = take_option(&mut args, "--device")
+        .or_else(|| config.get("brightness", "device").map(|d| d.to_string()));
+
+    let fade = take_option(&mut args, "--fade").map(|ms| {
+        let mut fade = Fade::new(ms.parse().unwrap_or_else(|_| help()));
+        if let Some(steps) = take_option(&mut args, "--fade-steps") {
+            fade = fade.steps(steps.parse().unwrap_or_else(|_| help()));
+        }
+        if let Some(easing) = take_option(&mut args, "--easing") {
+            fade = fade.easing(easing.parse::<Easing>().unwrap_or_else(|_| help()));
+        }
+        fade
+    });
+
+    let raw = take_flag(&mut args, "--raw");
+    let linear = take_flag(&mut args, "--linear");
+
+    let exponent = take_option(&mut args, "--exponent")
+        .map(|e| e.parse::<Curve>().unwrap_or_else(|_| help()));
+
+    let interval = take_option(&mut args, "--interval")
+        .map(|i| i.parse::<u64>().unwrap_or_else(|_| help()));
+
+    let allow_off = take_flag(&mut args, "--allow-off");
+    let floor = take_option(&mut args, "--floor")
+        .map(|f| f.parse::<Floor>().unwrap_or_else(|_| help()));
+
+    let mut args = args.into_iter();
 
     let command = args.next();
 
-    let percent: Option<f64> = args.next().and_then(|p| p.parse().ok());
+    if command.as_ref().map(|c| c == "list").unwrap_or(false) {
+        list_brightness(config);
+        return;
+    }
+
+    let value: Option<f64> = args.next().and_then(|p| p.parse().ok());
+
+    let dbus = dbus_options(config);
+    let device_name = device.as_ref().map(|d| d as &str);
+    let mut bright_control = match AnyBrightness::probe(forced, device_name, &sysfs_root(config), &dbus) {
+        Ok(b) => b,
+        Err(e) => {
+            writeln!(stderr(), "Could not open a brightness backend: {}", e).ok();
+            exit(2)
+        },
+    };
+
+    // Scaling gamma ramps already dims on a perceptual scale.
+    let curve = match exponent {
+        _ if linear => Curve::linear(),
+        Some(curve) => curve,
+        None if bright_control.backend() == Backend::Gamma => Curve::linear(),
+        None => Curve::default(),
+    };
+
+    let command = command.as_ref().map(|a| a as &str);
+
+    if command == Some("save") || command == Some("restore") {
+        persist_brightness(&bright_control, command == Some("save"));
+        return;
+    }
+
+    if let (Some("get"), &AnyBrightness::Xcb(ref xcb)) = (command, &bright_control) {
+        let unlit = xcb.unlit();
+        let unlit_crtcs = unlit.as_ref().map(|g| g.crtcs()).unwrap_or(&[]);
+        if xcb.displays().len() + unlit_crtcs.len() > 1 {
+            for display in xcb.displays() {
+                match xcb.current_of(display) {
+                    Ok(c) if raw => println!("{}\t{}", display.name(), c),
+                    Ok(c) => println!("{}\t{}", display.name(),
+                                      100.0 * curve.to_perceived(c / 100.0)),
+                    Err(e) => {
+                        writeln!(stderr(), "Could not get brightness of {}: {}",
+                                 display.name(), e).ok();
+                    },
+                }
+            }
+            if let Some(ref gamma) = unlit {
+                for crtc in gamma.crtcs() {
+                    let name = crtc.outputs().join(",");
+                    // Raw levels are the ramp's, as the gamma backend prints them.
+                    match gamma.brightness_of(crtc) {
+                        Ok(b) if raw => println!("{}\t{}", name, 100.0 * b),
+                        Ok(b) => println!("{}\t{}", name,
+                                          100.0 * curve.to_perceived(gamma::light_for_level(b))),
+                        Err(e) => {
+                            writeln!(stderr(), "Could not get brightness of {}: {}",
+                                     name, e).ok();
+                        },
+                    }
+                }
+            }
+            return;
+        }
+    }
+
+    if let (Some("get"), &AnyBrightness::Gamma(ref gamma)) = (command, &bright_control) {
+        let gamma = match gamma.gamma() {
+            Ok(g) => g,
+            Err(e) => {
+                writeln!(stderr(), "Could not get brightness: {}", e).ok();
+                exit(3)
+            },
+        };
+        if gamma.crtcs().len() > 1 {
+            for crtc in gamma.crtcs() {
+                match gamma.brightness_of(crtc) {
+                    Ok(b) if raw => println!("{}\t{}", crtc.outputs().join(","), 100.0 * b),
+                    Ok(b) => println!("{}\t{}", crtc.outputs().join(","),
+                                      100.0 * curve.to_perceived(b)),
+                    Err(e) => {
+                        writeln!(stderr(), "Could not get brightness of {}: {}",
+                                 crtc.outputs().join(","), e).ok();
+                    },
+                }
+            }
+            return;
+        }
+    }
+
+    if let (Some("watch"), &mut AnyBrightness::Xcb(ref mut xcb)) = (command, &mut bright_control) {
+        watch_xcb_brightness(xcb, curve);
+    }
+
+    // Grab the sysfs watcher before `bright_control` is wrapped up below.
+    // xcb has been followed above, which leaves only dbus and gamma, with
+    // nothing to watch.
+    let watcher = match (command, &bright_control) {
+        (Some("watch"), &AnyBrightness::Fs(ref fs)) => {
+            Some(fs.watch(Duration::from_millis(interval.unwrap_or(1000))))
+        },
+        (Some("watch"), other) => {
+            writeln!(stderr(), "Watching brightness needs the fs, logind or xcb backend, not {}",
+                     other.backend().name()).ok();
+            exit(2)
+        },
+        _ => None,
+    };
+
+    let floor = if allow_off {
+        Floor::off()
+    } else {
+        floor.or_else(|| {
+            config.get_for("brightness", bright_control.device(), "floor")
+                .and_then(|f| f.parse().ok())
+        }).unwrap_or(DEFAULT_FLOOR)
+    };
+
+    if let Ok(max) = bright_control.max() {
+        if !floor.fits(max) {
+            writeln!(stderr(), "The brightness floor is not below the {} backend's maximum of {}",
+                     bright_control.backend().name(), max).ok();
+            exit(2)
+        }
+    }
+
+    let floored = Floored::new(&bright_control, floor);
+    let bright_control = Perceptual::new(&floored, curve);
+
+    if command == Some("auto") {
+        auto_brightness(&bright_control, config, interval);
+    }
+
+    if let Some(watcher) = watcher {
+        watch_brightness(&bright_control, watcher);
+    }
+
+    if raw {
+        change_brightness(&floored, command, value, fade);
+    } else {
+        change_brightness(&bright_control, command, value, fade);
+    }
+    if command == Some("get") {
+        return;
+    }
+
+    let current = match bright_control.current() {
+        Ok(c) => c as u32,
+        Err(e) => {
+            writeln!(stderr(), "Could not get brightness: {}", e).ok();
+            exit(4)
+        },
+    };
 
-    let bright_control = XcbBrightness::connect();
+    if let Err(e) = notify::brightness::show_brightness(current) {
+        writeln!(stderr(), "Error showing volume notification: {}", e).ok();
+    }
+}
 
-    let result = match (command.as_ref().map(|a| a as &str), percent) {
-        (Some("up"),   Some(p)) => bright_control.change_n_clip(p),
-        (Some("down"), Some(p)) => bright_control.change_n_clip(-1.0 * p),
-        (Some("set"),  Some(p)) => bright_control.set(p),
-        (Some("get"),  None)    => {
+/// Runs `up`, `down`, `set` or `get` with `value` in `bright_control`'s
+/// units.
+fn change_brightness<B: Brightness>(bright_control: &B, command: Option<&str>,
+                                    value: Option<f64>, fade: Option<Fade>) {
+    let result = match (command, value, fade) {
+        (Some("up"),   Some(p), None) => bright_control.change_n_clip(p),
+        (Some("down"), Some(p), None) => bright_control.change_n_clip(-1.0 * p),
+        (Some("set"),  Some(p), None) => bright_control.set(p),
+        (Some("up"),   Some(p), Some(f)) => bright_control.current()
+            .and_then(|c| bright_control.fade_to(c + p, &f)),
+        (Some("down"), Some(p), Some(f)) => bright_control.current()
+            .and_then(|c| bright_control.fade_to(c - p, &f)),
+        (Some("set"),  Some(p), Some(f)) => bright_control.fade_to(p, &f),
+        (Some("get"),  None, _) => {
             let current = match bright_control.current() {
                 Ok(c) => c,
                 Err(e) => {
@@ -129,16 +840,357 @@ fn set_brightness() {
     if let Err(e) = result {
         writeln!(stderr(), "Error during operation: {}", e).ok();
     }
+}
 
-    let current = match bright_control.current() {
-        Ok(c) => c as u32,
+/// Moves `kbd` by `percent` of its range, rounding away from the current
+/// level.
+fn step_kbd_brightness(kbd: &FsBrightness, percent: f64) -> Result<(), fs::Error> {
+    let (min, max) = (try!(kbd.min()), try!(kbd.max()));
+    let delta = percent / 100f64 * (max - min);
+    kbd.change_with(|current| {
+        if delta > 0.0 {
+            (current + delta).ceil()
+        } else {
+            (current + delta).floor()
+        }
+    })
+}
+
+/// Turns `kbd` off, remembering its level in the state file, or back on at
+/// the remembered level.
+fn toggle_kbd_brightness(kbd: &FsBrightness) -> Result<(), fs::Error> {
+    let mut state = load_state();
+    let key = format!("kbd-brightness.{}", kbd.name().unwrap_or("default"));
+
+    let current = try!(kbd.current());
+    if current > 0.0 {
+        state.set(key, current);
+        save_state(&state);
+        return kbd.set(0.0);
+    }
+
+    match state.get(&key).and_then(|v| v.parse::<f64>().ok()) {
+        Some(level) if level > 0.0 => kbd.set(try!(kbd.clip(level))),
+        _ => kbd.set_percent(100.0),
+    }
+}
+
+/// Follows the ambient light sensor forever.
+fn auto_brightness<B: Brightness>(bright_control: &B, config: &Config, interval: Option<u64>) -> ! {
+    let section = "auto-brightness";
+
+    let sensor = match LightSensor::find(&sysfs_root(config).join(light::IIO_DIR), config.get(section, "sensor")) {
+        Ok(s) => s,
         Err(e) => {
-            writeln!(stderr(), "Could not get brightness: {}", e).ok();
+            writeln!(stderr(), "Could not open a light sensor: {}", e).ok();
+            exit(2)
+        },
+    };
+
+    let lux_curve = config.get(section, "curve")
+        .map(|c| c.parse::<LuxCurve>().unwrap_or_else(|_| {
+            writeln!(stderr(), "Invalid auto-brightness curve: {}", c).ok();
+            exit(2)
+        }))
+        .unwrap_or_default();
+
+    let mut auto = AutoBrightness::new(lux_curve);
+    if let Some(smoothing) = config.get(section, "smoothing").and_then(|s| s.parse().ok()) {
+        auto = auto.smoothing(smoothing);
+    }
+    if let Some(hysteresis) = config.get(section, "hysteresis").and_then(|h| h.parse().ok()) {
+        auto = auto.hysteresis(hysteresis);
+    }
+
+    let interval = interval
+        .or_else(|| config.get(section, "interval").and_then(|i| i.parse().ok()))
+        .unwrap_or(1000);
+
+    writeln!(stderr(), "Following light sensor {}", sensor.name()).ok();
+
+    loop {
+        match sensor.lux() {
+            Ok(lux) => {
+                if let Err(e) = auto.step(bright_control, lux) {
+                    writeln!(stderr(), "Could not set brightness: {}", e).ok();
+                }
+            },
+            Err(e) => {
+                writeln!(stderr(), "Could not read light sensor: {}", e).ok();
+            },
+        }
+        sleep(Duration::from_millis(interval));
+    }
+}
+
+/// Prints each change to the backlight as `old new` in backend units and
+/// shows the brightness notification.
+fn watch_brightness<B: Brightness>(bright_control: &B, watcher: Watcher) -> ! {
+    for change in watcher {
+        match change {
+            Ok(change) => println!("{} {}", change.old, change.new),
+            Err(e) => {
+                writeln!(stderr(), "Could not read brightness: {}", e).ok();
+                continue;
+            },
+        }
+        match bright_control.current() {
+            Ok(c) => {
+                if let Err(e) = notify::brightness::show_brightness(c.round() as u32) {
+                    writeln!(stderr(), "Error showing brightness notification: {}", e).ok();
+                }
+            },
+            Err(e) => {
+                writeln!(stderr(), "Could not get brightness: {}", e).ok();
+            },
+        }
+    }
+    unreachable!()
+}
+
+/// Prints `output percent` whenever a RandR backlight changes and shows the
+/// brightness notification, following outputs as they are plugged in.
+fn watch_xcb_brightness(xcb: &mut XcbBrightness, curve: Curve) -> ! {
+    if let Err(e) = xcb.subscribe() {
+        writeln!(stderr(), "Could not subscribe to RandR events: {}", e).ok();
+        exit(2)
+    }
+
+    loop {
+        let events = match xcb.wait_events() {
+            Ok(e) => e,
+            Err(e) => {
+                writeln!(stderr(), "Could not wait for RandR events: {}", e).ok();
+                exit(1)
+            },
+        };
+
+        for event in events {
+            match event {
+                XcbEvent::Changed(name, percent) => {
+                    println!("{} {}", name, percent);
+                    let shown = 100.0 * curve.to_perceived(percent / 100.0);
+                    if let Err(e) = notify::brightness::show_brightness(shown.round() as u32) {
+                        writeln!(stderr(), "Error showing brightness notification: {}", e).ok();
+                    }
+                },
+                XcbEvent::OutputsChanged => {
+                    let names: Vec<&str> = xcb.displays().iter().map(|d| d.name()).collect();
+                    writeln!(stderr(), "Outputs changed, now controlling: {}",
+                             names.join(", ")).ok();
+                },
+            }
+        }
+    }
+}
+
+fn list_brightness(config: &Config) {
+    let devices = match fs::devices(&sysfs_root(config).join(fs::BACKLIGHT_DIR)) {
+        Ok(d) => d,
+        Err(e) => {
+            writeln!(stderr(), "Could not list backlight devices: {}", e).ok();
+            exit(3)
+        },
+    };
+
+    for device in devices {
+        let control = FsBrightness::at(&device.path);
+        let current = control.current().map(|c| c.to_string()).unwrap_or("?".to_string());
+        let max = control.max().map(|m| m.to_string()).unwrap_or("?".to_string());
+        println!("{}\t{}\t{}\t{}", device.name, device.kind.name(), current, max);
+    }
+}
+
+fn set_kbd_brightness(config: &Config) {
+    let mut args: Vec<String> = args().skip(2).collect();
+
+    let device = take_option(&mut args, "--device");
+
+    let mut args = args.into_iter();
+    let command = args.next();
+    let percent: Option<f64> = args.next().and_then(|p| p.parse().ok());
+
+    let kbd = match FsBrightness::find_kbd(&sysfs_root(config), device.as_ref().map(|d| d as &str)) {
+        Ok(k) => k,
+        Err(e) => {
+            writeln!(stderr(), "Could not find a keyboard backlight: {}", e).ok();
+            exit(2)
+        },
+    };
+    let kbd = if kbd.writable() {
+        kbd
+    } else {
+        match kbd.through_logind(&dbus_options(config)) {
+            Ok(k) => k,
+            Err(e) => {
+                writeln!(stderr(), "Could not connect to logind: {}", e).ok();
+                exit(2)
+            },
+        }
+    };
+
+    // Keyboards usually only have a handful of levels, so without a
+    // percentage up and down move a single level, and with one they move
+    // at least a level rather than rounding back to where they were.
+    let result = match (command.as_ref().map(|c| c as &str), percent) {
+        (Some("up"),     Some(p)) => step_kbd_brightness(&kbd, p),
+        (Some("down"),   Some(p)) => step_kbd_brightness(&kbd, -p),
+        (Some("up"),     None)    => kbd.change_n_clip(1.0),
+        (Some("down"),   None)    => kbd.change_n_clip(-1.0),
+        (Some("set"),    Some(p)) => kbd.set_percent(p),
+        (Some("toggle"), None)    => toggle_kbd_brightness(&kbd),
+        (Some("get"),    None)    => {
+            match kbd.percent() {
+                Ok(c) => println!("{}", c),
+                Err(e) => {
+                    writeln!(stderr(), "Could not get keyboard brightness: {}", e).ok();
+                    exit(3)
+                },
+            }
+            return;
+        },
+        _ => help(),
+    };
+
+    if let Err(e) = result {
+        writeln!(stderr(), "Error during operation: {}", e).ok();
+    }
+
+    let current = match kbd.percent() {
+        Ok(c) => c.round() as u32,
+        Err(e) => {
+            writeln!(stderr(), "Could not get keyboard brightness: {}", e).ok();
             exit(4)
         },
     };
 
-    if let Err(e) = notify::brightness::show_brightness(current) {
-        writeln!(stderr(), "Error showing volume notification: {}", e).ok();
+    if let Err(e) = notify::brightness::show_kbd_brightness(current) {
+        writeln!(stderr(), "Error showing keyboard brightness notification: {}", e).ok();
+    }
+}
+
+fn set_color_temp(config: &Config) {
+    let mut args: Vec<String> = args().skip(2).collect();
+
+    let device = take_option(&mut args, "--device");
+    let device = device.as_ref().map(|d| d as &str);
+
+    let mut args = args.into_iter();
+    let kelvin = match (args.next().as_ref().map(|c| c as &str), args.next()) {
+        (Some("set"), Some(k)) => k.parse::<f64>().unwrap_or_else(|_| help()),
+        (Some("reset"), None) => color::NEUTRAL,
+        (Some("schedule"), None) => schedule_color_temp(device, config),
+        _ => help(),
+    };
+
+    let session = match GammaBrightness::connect(device) {
+        Ok(s) => s,
+        Err(e) => {
+            writeln!(stderr(), "Could not open the X server's gamma ramps: {}", e).ok();
+            exit(2)
+        },
+    };
+
+    if let Err(e) = session.gamma().and_then(|g| g.set_temperature(kelvin)) {
+        writeln!(stderr(), "Could not set the colour temperature: {}", e).ok();
+        exit(1)
+    }
+}
+
+/// Seconds between looks at the clock while following a night light
+/// schedule.
+const NIGHT_LIGHT_TICK: u64 = 5;
+
+/// Follows the `[night-light]` schedule forever, sharing the backlight's
+/// RandR connection when there is one.
+fn schedule_color_temp(device: Option<&str>, config: &Config) -> ! {
+    let section = "night-light";
+
+    let parse_or_exit = |key: &str, default: &str| -> TimeOfDay {
+        let value = config.get(section, key).unwrap_or(default);
+        value.parse().unwrap_or_else(|_| {
+            writeln!(stderr(), "Invalid night light {} time: {}", key, value).ok();
+            exit(2)
+        })
+    };
+    let latitude = config.get(section, "latitude").and_then(|l| l.parse().ok());
+    let longitude = config.get(section, "longitude").and_then(|l| l.parse().ok());
+    let times = match (latitude, longitude) {
+        (Some(lat), Some(long)) => Times::Solar(lat, long),
+        _ => Times::Fixed(parse_or_exit("dawn", night::DEFAULT_DAWN),
+                          parse_or_exit("dusk", night::DEFAULT_DUSK)),
+    };
+
+    let mut schedule = Schedule::new(times);
+    if let Some(day) = config.get(section, "day").and_then(|d| d.parse().ok()) {
+        schedule = schedule.day(day);
+    }
+    if let Some(night) = config.get(section, "night").and_then(|n| n.parse().ok()) {
+        schedule = schedule.night(night);
+    }
+    if let Some(minutes) = config.get(section, "transition").and_then(|t| t.parse().ok()) {
+        schedule = schedule.transition(minutes);
+    }
+
+    let interval = config.get(section, "interval")
+        .and_then(|i| i.parse().ok())
+        .unwrap_or(60);
+
+    let xcb = XcbBrightness::connect().and_then(|mut x| x.select(device).map(|_| x));
+    match xcb {
+        Ok(xcb) => follow_schedule(|| xcb.gamma(), &schedule, interval),
+        Err(_) => match GammaBrightness::connect(device) {
+            Ok(session) => follow_schedule(|| session.gamma(), &schedule, interval),
+            Err(e) => {
+                writeln!(stderr(), "Could not open the X server's gamma ramps: {}", e).ok();
+                exit(2)
+            },
+        },
+    }
+}
+
+/// Applies `schedule` whenever its temperature moves, and at least every
+/// `interval` seconds because suspend, VT switches and hotplug reset the
+/// ramps. A tick that took far longer than it should have by the wall
+/// clock means the machine was asleep, and is applied straight away.
+fn follow_schedule<'a, F>(gamma: F, schedule: &Schedule, interval: u64) -> !
+    where F: Fn() -> Result<Gamma<'a>, XcbError>
+{
+    let mut applied: Option<(f64, SystemTime)> = None;
+    let mut last_tick = SystemTime::now();
+
+    loop {
+        let now = SystemTime::now();
+        let kelvin = match schedule.now() {
+            Ok(k) => k.round(),
+            Err(e) => {
+                writeln!(stderr(), "Could not read the local time: {}", e).ok();
+                exit(1)
+            },
+        };
+
+        let resumed = now.duration_since(last_tick)
+            .map(|d| d.as_secs() > NIGHT_LIGHT_TICK * 3)
+            .unwrap_or(true);
+        let due = match applied {
+            Some((old, at)) => {
+                old != kelvin || resumed || now.duration_since(at)
+                    .map(|d| d.as_secs() >= interval)
+                    .unwrap_or(true)
+            },
+            None => true,
+        };
+
+        if due {
+            match gamma().and_then(|g| g.set_temperature(kelvin)) {
+                Ok(()) => applied = Some((kelvin, now)),
+                Err(e) => {
+                    writeln!(stderr(), "Could not set the colour temperature: {}", e).ok();
+                },
+            }
+        }
+
+        last_tick = now;
+        sleep(Duration::from_secs(NIGHT_LIGHT_TICK));
     }
 }
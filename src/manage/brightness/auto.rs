@@ -0,0 +1,227 @@
+use std::str::FromStr;
+
+use super::Brightness;
+
+/// Lux to brightness percent, roughly following how bright a room looks.
+pub const DEFAULT_CURVE: &'static str = "0:5, 10:20, 100:40, 1000:75, 10000:100";
+/// How much of each new reading goes into the smoothed lux value.
+pub const DEFAULT_SMOOTHING: f64 = 0.3;
+/// Brightness changes smaller than this many percent are skipped.
+pub const DEFAULT_HYSTERESIS: f64 = 3.0;
+
+/// A piecewise linear map from lux to brightness percent.
+#[derive(Debug, Clone)]
+pub struct LuxCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl LuxCurve {
+    pub fn brightness(&self, lux: f64) -> f64 {
+        let first = self.points[0];
+        if lux <= first.0 {
+            return first.1;
+        }
+        for pair in self.points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if lux <= x1 {
+                return y0 + (y1 - y0) * (lux - x0) / (x1 - x0);
+            }
+        }
+        self.points[self.points.len() - 1].1
+    }
+}
+
+impl Default for LuxCurve {
+    fn default() -> Self {
+        DEFAULT_CURVE.parse().unwrap()
+    }
+}
+
+impl FromStr for LuxCurve {
+    type Err = ();
+
+    /// Parses comma separated `lux:percent` points.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut points = Vec::new();
+        for point in s.split(',') {
+            let mut parts = point.split(':');
+            let lux: f64 = try!(parts.next().and_then(|l| l.trim().parse().ok()).ok_or(()));
+            let percent: f64 = try!(parts.next().and_then(|p| p.trim().parse().ok()).ok_or(()));
+            points.push((lux, percent));
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+        if points.is_empty() {
+            return Err(());
+        }
+        Ok(LuxCurve {
+            points: points,
+        })
+    }
+}
+
+/// Drives a `Brightness` from light sensor readings. Changes made by
+/// anyone else between steps are kept as a bias on top of the curve
+/// instead of being undone, limited so the biased curve stays within
+/// 0 - 100%.
+pub struct AutoBrightness {
+    curve: LuxCurve,
+    smoothing: f64,
+    hysteresis: f64,
+    bias: f64,
+    lux: Option<f64>,
+    last_set: Option<f64>,
+}
+
+impl AutoBrightness {
+    pub fn new(curve: LuxCurve) -> Self {
+        AutoBrightness {
+            curve: curve,
+            smoothing: DEFAULT_SMOOTHING,
+            hysteresis: DEFAULT_HYSTERESIS,
+            bias: 0.0,
+            lux: None,
+            last_set: None,
+        }
+    }
+
+    pub fn smoothing(mut self, smoothing: f64) -> Self {
+        self.smoothing = smoothing.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis.max(0.0);
+        self
+    }
+
+    /// Feeds in a new reading and adjusts `control`, which must work in
+    /// percent.
+    pub fn step<B: Brightness>(&mut self, control: &B, lux: f64) -> Result<(), B::E> {
+        let lux = match self.lux {
+            Some(old) => old + self.smoothing * (lux - old),
+            None => lux,
+        };
+        self.lux = Some(lux);
+
+        let current = try!(control.current());
+        if let Some(last) = self.last_set {
+            if (current - last).abs() > 0.5 {
+                self.bias += current - last;
+            }
+        }
+
+        let base = self.curve.brightness(lux).max(0.0).min(100.0);
+        self.bias = self.bias.max(-base).min(100.0 - base);
+        let target = base + self.bias;
+        if (target - current).abs() >= self.hysteresis {
+            try!(control.set(target));
+            self.last_set = Some(try!(control.current()));
+        } else {
+            self.last_set = Some(current);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::FakeBrightness;
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve: LuxCurve = "0:10, 100:50, 1000:90".parse().unwrap();
+        assert_eq!(curve.brightness(0.0), 10.0);
+        assert_eq!(curve.brightness(50.0), 30.0);
+        assert_eq!(curve.brightness(100.0), 50.0);
+        assert_eq!(curve.brightness(550.0), 70.0);
+    }
+
+    #[test]
+    fn holds_the_ends_outside_the_curve() {
+        let curve: LuxCurve = "10:20, 100:40".parse().unwrap();
+        assert_eq!(curve.brightness(0.0), 20.0);
+        assert_eq!(curve.brightness(100000.0), 40.0);
+    }
+
+    #[test]
+    fn sorts_points_by_lux() {
+        let curve: LuxCurve = "100:50, 0:10".parse().unwrap();
+        assert_eq!(curve.brightness(50.0), 30.0);
+    }
+
+    #[test]
+    fn rejects_bad_curves() {
+        assert!("".parse::<LuxCurve>().is_err());
+        assert!("10".parse::<LuxCurve>().is_err());
+        assert!("10:bright".parse::<LuxCurve>().is_err());
+    }
+
+    #[test]
+    fn the_default_curve_parses() {
+        assert_eq!(LuxCurve::default().brightness(10000.0), 100.0);
+    }
+
+    #[test]
+    fn follows_the_curve() {
+        let control = FakeBrightness::new(0.0, 100.0, 0.0);
+        let mut auto = AutoBrightness::new("0:0, 100:100".parse().unwrap());
+        auto.step(&control, 60.0).unwrap();
+        assert_eq!(control.value.get(), 60.0);
+    }
+
+    #[test]
+    fn smooths_readings() {
+        let control = FakeBrightness::new(0.0, 100.0, 0.0);
+        let mut auto = AutoBrightness::new("0:0, 100:100".parse().unwrap())
+            .smoothing(0.5)
+            .hysteresis(0.0);
+        auto.step(&control, 20.0).unwrap();
+        auto.step(&control, 80.0).unwrap();
+        assert_eq!(control.value.get(), 50.0);
+    }
+
+    #[test]
+    fn skips_changes_smaller_than_the_hysteresis() {
+        let control = FakeBrightness::new(0.0, 100.0, 0.0);
+        let mut auto = AutoBrightness::new("0:0, 100:100".parse().unwrap())
+            .smoothing(1.0)
+            .hysteresis(5.0);
+        auto.step(&control, 50.0).unwrap();
+        auto.step(&control, 54.0).unwrap();
+        assert_eq!(control.value.get(), 50.0);
+        auto.step(&control, 56.0).unwrap();
+        assert_eq!(control.value.get(), 56.0);
+    }
+
+    #[test]
+    fn keeps_manual_changes_as_a_bias() {
+        let control = FakeBrightness::new(0.0, 100.0, 0.0);
+        let mut auto = AutoBrightness::new("0:0, 100:100".parse().unwrap())
+            .smoothing(1.0)
+            .hysteresis(0.0);
+        auto.step(&control, 50.0).unwrap();
+        control.value.set(60.0);
+        auto.step(&control, 30.0).unwrap();
+        assert_eq!(control.value.get(), 40.0);
+    }
+
+    #[test]
+    fn limits_the_bias_to_the_curve_range() {
+        let control = FakeBrightness::new(0.0, 100.0, 0.0);
+        let mut auto = AutoBrightness::new("0:0, 100:100".parse().unwrap())
+            .smoothing(1.0)
+            .hysteresis(0.0);
+        auto.step(&control, 10.0).unwrap();
+        control.value.set(100.0);
+        auto.step(&control, 10.0).unwrap();
+        assert_eq!(control.value.get(), 100.0);
+
+        // Brighter light can only use up as much bias as fits below 100%,
+        // and the rest is dropped rather than banked for later.
+        auto.step(&control, 90.0).unwrap();
+        assert_eq!(control.value.get(), 100.0);
+        auto.step(&control, 10.0).unwrap();
+        assert_eq!(control.value.get(), 20.0);
+    }
+}
@@ -1,49 +1,274 @@
 extern crate dbus;
 
-use self::dbus::{Connection, BusType, Message};
-use self::dbus::Error as DbusError;
+use std::str::FromStr;
+
+use self::dbus::{Connection, BusType, Message, MessageItem, Props};
+pub use self::dbus::Error as DbusError;
 
 use super::Brightness;
 
+pub const DEFAULT_TIMEOUT_MS: i32 = 2000;
+
+const GNOME_DESTINATION: &'static str = "org.gnome.SettingsDaemon.Power";
+const GNOME_LEGACY_DESTINATION: &'static str = "org.gnome.SettingsDaemon";
+const GNOME_PATH: &'static str = "/org/gnome/SettingsDaemon/Power";
+const GNOME_INTERFACE: &'static str = "org.gnome.SettingsDaemon.Power.Screen";
+
+const KDE_DESTINATION: &'static str = "org.kde.Solid.PowerManagement";
+const KDE_PATH: &'static str = "/org/kde/Solid/PowerManagement/Actions/BrightnessControl";
+const KDE_INTERFACE: &'static str = "org.kde.Solid.PowerManagement.Actions.BrightnessControl";
+
+/// The brightness interfaces of the desktop daemons we know how to drive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Api {
+    /// Current gnome-settings-daemon's `Brightness` property.
+    GnomeProperty,
+    /// Older gnome-settings-daemon's `GetPercentage` and `SetPercentage`.
+    GnomeMethods,
+    /// KDE PowerDevil's brightness control action, in its own units.
+    PowerDevil,
+}
+
+/// Newest first, so a daemon that still answers the old methods is
+/// driven through the new ones.
+const DETECT_ORDER: [Api; 3] = [Api::GnomeProperty, Api::GnomeMethods, Api::PowerDevil];
+
+impl Api {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Api::GnomeProperty => "gnome",
+            Api::GnomeMethods => "gnome-legacy",
+            Api::PowerDevil => "powerdevil",
+        }
+    }
+}
+
+/// The bus a brightness service lives on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bus {
+    Session,
+    System,
+    /// A D-Bus address such as `unix:path=/tmp/test-bus`.
+    Address(String),
+}
+
+impl FromStr for Bus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "session" => Ok(Bus::Session),
+            "system" => Ok(Bus::System),
+            address if address.contains(':') => Ok(Bus::Address(address.to_string())),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where to find a brightness service and how long to wait for it. The
+/// destination, path and interface replace those of whichever API is
+/// detected, for daemons that speak one of them under another name.
+/// `logind` is where the fs backend finds systemd-logind when it can't
+/// write sysfs itself.
+#[derive(Debug, Clone)]
+pub struct DbusOptions {
+    bus: Bus,
+    logind: Bus,
+    timeout_ms: i32,
+    destination: Option<String>,
+    path: Option<String>,
+    interface: Option<String>,
+}
+
+impl Default for DbusOptions {
+    fn default() -> Self {
+        DbusOptions {
+            bus: Bus::Session,
+            logind: Bus::System,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            destination: None,
+            path: None,
+            interface: None,
+        }
+    }
+}
+
+impl DbusOptions {
+    pub fn bus(mut self, bus: Bus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    pub fn logind(mut self, bus: Bus) -> Self {
+        self.logind = bus;
+        self
+    }
+
+    pub fn logind_bus(&self) -> &Bus {
+        &self.logind
+    }
+
+    pub fn timeout_ms(&self) -> i32 {
+        self.timeout_ms
+    }
+
+    pub fn timeout(mut self, ms: i32) -> Self {
+        self.timeout_ms = ms;
+        self
+    }
+
+    pub fn destination(mut self, destination: Option<String>) -> Self {
+        self.destination = destination;
+        self
+    }
+
+    pub fn path(mut self, path: Option<String>) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn interface(mut self, interface: Option<String>) -> Self {
+        self.interface = interface;
+        self
+    }
+}
+
 pub struct DbusBrightness {
     connection: Connection,
+    options: DbusOptions,
+    api: Api,
 }
 
 #[allow(dead_code)]
 impl DbusBrightness {
+    /// Connects to the session bus and uses the first API that answers.
     pub fn new() -> Result<Self, DbusError> {
-        Ok(DbusBrightness {
-            connection: try!(Connection::get_private(BusType::Session)),
-        })
+        DbusBrightness::open(DbusOptions::default())
+    }
+
+    /// Connects to the bus in `options` and uses the first API that
+    /// answers there.
+    pub fn open(options: DbusOptions) -> Result<Self, DbusError> {
+        let connection = try!(connect(&options.bus));
+        DbusBrightness::detect(connection, options)
+    }
+
+    /// Uses the first API that answers on `connection`.
+    fn detect(connection: Connection, options: DbusOptions) -> Result<Self, DbusError> {
+        let (mut connection, mut options) = (connection, options);
+
+        let mut last_error = None;
+        for api in &DETECT_ORDER {
+            let control = DbusBrightness {
+                connection: connection,
+                options: options,
+                api: *api,
+            };
+            match control.current() {
+                Ok(_) => return Ok(control),
+                Err(e) => last_error = Some(e),
+            }
+            connection = control.connection;
+            options = control.options;
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            DbusError::new_custom("NO_SERVICE", "no brightness service on the bus")
+        }))
+    }
+
+    pub fn api(&self) -> Api {
+        self.api
     }
 
     fn send(&self, msg: Message) -> Result<Message, DbusError> {
-		self.connection.send_with_reply_and_block(msg, 2000)
+        self.connection.send_with_reply_and_block(msg, self.options.timeout_ms)
+    }
+
+    /// The destination, path and interface of the API in use, after the
+    /// overrides in the options.
+    fn endpoint(&self) -> (&str, &str, &str) {
+        let (destination, path, interface) = match self.api {
+            Api::GnomeProperty => (GNOME_DESTINATION, GNOME_PATH, GNOME_INTERFACE),
+            Api::GnomeMethods => (GNOME_LEGACY_DESTINATION, GNOME_PATH, GNOME_INTERFACE),
+            Api::PowerDevil => (KDE_DESTINATION, KDE_PATH, KDE_INTERFACE),
+        };
+        (self.options.destination.as_ref().map(|d| d as &str).unwrap_or(destination),
+         self.options.path.as_ref().map(|p| p as &str).unwrap_or(path),
+         self.options.interface.as_ref().map(|i| i as &str).unwrap_or(interface))
+    }
+
+    fn message(&self, method: &str) -> Result<Message, DbusError> {
+        let (destination, path, interface) = self.endpoint();
+        Message::new_method_call(destination, path, interface, method)
+            .map_err(|s| DbusError::new_custom("CREATE_MESSAGE", &s))
     }
+
+    fn call(&self, method: &str) -> Result<Message, DbusError> {
+        let rpc = try!(self.message(method));
+        self.send(rpc)
+    }
+
+    fn gnome_property<'a>(&'a self) -> Props<'a> {
+        let (destination, path, interface) = self.endpoint();
+        Props::new(&self.connection, destination, path, interface, self.options.timeout_ms)
+    }
+
+    fn kde_get(&self, method: &str) -> Result<f64, DbusError> {
+        let response = try!(self.call(method));
+        response.get1::<i32>()
+            .map(|level| level as f64)
+            .ok_or_else(|| no_return("an int"))
+    }
+}
+
+/// Opens a private connection to `bus`.
+pub fn connect(bus: &Bus) -> Result<Connection, DbusError> {
+    match *bus {
+        Bus::Session => Connection::get_private(BusType::Session),
+        Bus::System => Connection::get_private(BusType::System),
+        Bus::Address(ref address) => {
+            let connection = try!(Connection::open_private(address));
+            try!(connection.register());
+            Ok(connection)
+        },
+    }
+}
+
+fn no_return(kind: &str) -> DbusError {
+    DbusError::new_custom("NO_RETURN",
+        &format!("dbus method call did not return {}", kind))
 }
 
 impl Brightness for DbusBrightness {
     type E = DbusError;
 
     fn current(&self) -> Result<f64, Self::E> {
-		let rpc = match Message::new_method_call(
-                "org.gnome.SettingsDaemon",
-                "/org/gnome/SettingsDaemon/Power",
-                "org.gnome.SettingsDaemon.Power.Screen",
-                "GetPercentage") {
-            Ok(m) => m,
-            Err(s) => return Err(DbusError::new_custom("CREATE_MESSAGE", &s)),
-        };
-		let response = try!(self.send(rpc));
-		match response.get1::<u32>() {
-            Some(level) => Ok(level as f64),
-            None => Err(DbusError::new_custom("NO_RETURN",
-                "dbus method call did not return an unsigned int")),
+        match self.api {
+            Api::GnomeProperty => {
+                match try!(self.gnome_property().get("Brightness")) {
+                    // -1 means the daemon found no backlight to control.
+                    MessageItem::Int32(level) if level >= 0 => Ok(level as f64),
+                    MessageItem::Int32(_) => Err(DbusError::new_custom("NO_BACKLIGHT",
+                        "the settings daemon has no backlight to control")),
+                    _ => Err(no_return("an int")),
+                }
+            },
+            Api::GnomeMethods => {
+                let response = try!(self.call("GetPercentage"));
+                response.get1::<u32>()
+                    .map(|level| level as f64)
+                    .ok_or_else(|| no_return("an unsigned int"))
+            },
+            Api::PowerDevil => self.kde_get("brightness"),
         }
     }
 
     fn max(&self) -> Result<f64, Self::E> {
-        Ok(100f64)
+        match self.api {
+            Api::PowerDevil => self.kde_get("brightnessMax"),
+            _ => Ok(100f64),
+        }
     }
 
     fn min(&self) -> Result<f64, Self::E> {
@@ -51,15 +276,195 @@ impl Brightness for DbusBrightness {
     }
 
     fn set(&self, value: f64) -> Result<(), Self::E> {
-		let rpc = match Message::new_method_call(
-                "org.gnome.SettingsDaemon",
-                "/org/gnome/SettingsDaemon/Power",
-                "org.gnome.SettingsDaemon.Power.Screen",
-                "SetPercentage") {
-            Ok(m) => m,
-            Err(s) => return Err(DbusError::new_custom("CREATE_MESSAGE", &s)),
+        let value = value.round();
+        match self.api {
+            Api::GnomeProperty => {
+                self.gnome_property().set("Brightness", MessageItem::Int32(value as i32))
+            },
+            Api::GnomeMethods => {
+                let rpc = try!(self.message("SetPercentage")).append1(value as u32);
+                self.send(rpc).map(|_| ())
+            },
+            Api::PowerDevil => {
+                let rpc = try!(self.message("setBrightness")).append1(value as i32);
+                self.send(rpc).map(|_| ())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use super::*;
+    use super::dbus::MessageItem;
+    use super::super::Brightness;
+    use testing::DbusDaemon;
+
+    fn unknown(call: &Message) -> Message {
+        let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+        Message::new_error(call, "org.freedesktop.DBus.Error.UnknownMethod", &member).unwrap()
+    }
+
+    /// Answers as gnome-settings-daemon's `Brightness` property would.
+    fn gnome_property(call: &Message, level: &Mutex<i32>) -> Message {
+        let items = call.get_items();
+        let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+        match (&member as &str, items.get(1), items.get(2)) {
+            ("Get", Some(&MessageItem::Str(ref p)), _) if p == "Brightness" => {
+                let level = *level.lock().unwrap();
+                call.method_return().append(MessageItem::Variant(Box::new(MessageItem::Int32(level))))
+            },
+            ("Set", Some(&MessageItem::Str(ref p)), Some(&MessageItem::Variant(ref v)))
+                if p == "Brightness" =>
+            {
+                if let MessageItem::Int32(value) = **v {
+                    *level.lock().unwrap() = value;
+                }
+                call.method_return()
+            },
+            _ => unknown(call),
+        }
+    }
+
+    /// Answers as older gnome-settings-daemon's methods would.
+    fn gnome_methods(call: &Message, level: &Mutex<u32>) -> Message {
+        match call.member().map(|m| m.to_string()) {
+            Some(ref m) if m == "GetPercentage" => call.method_return().append1(*level.lock().unwrap()),
+            Some(ref m) if m == "SetPercentage" => match call.get1::<u32>() {
+                Some(value) => {
+                    *level.lock().unwrap() = value;
+                    call.method_return()
+                },
+                None => unknown(call),
+            },
+            _ => unknown(call),
+        }
+    }
+
+    /// Answers as PowerDevil's brightness control would, out of 1000.
+    fn powerdevil(call: &Message, level: &Mutex<i32>) -> Message {
+        match call.member().map(|m| m.to_string()) {
+            Some(ref m) if m == "brightness" => call.method_return().append1(*level.lock().unwrap()),
+            Some(ref m) if m == "brightnessMax" => call.method_return().append1(1000i32),
+            Some(ref m) if m == "setBrightness" => match call.get1::<i32>() {
+                Some(value) => {
+                    *level.lock().unwrap() = value;
+                    call.method_return()
+                },
+                None => unknown(call),
+            },
+            _ => unknown(call),
+        }
+    }
+
+    fn on(daemon: &DbusDaemon) -> DbusOptions {
+        DbusOptions::default().bus(Bus::Address(daemon.address().to_string()))
+    }
+
+    #[test]
+    fn drives_the_gnome_property() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let level = Arc::new(Mutex::new(40));
+        let served = level.clone();
+        daemon.serve(GNOME_DESTINATION, &[GNOME_PATH], move |call| gnome_property(call, &served));
+
+        let control = DbusBrightness::open(on(&daemon)).unwrap();
+        assert_eq!(control.api(), Api::GnomeProperty);
+        assert_eq!(control.current().unwrap(), 40.0);
+        assert_eq!(control.max().unwrap(), 100.0);
+
+        control.set(70.0).unwrap();
+        assert_eq!(*level.lock().unwrap(), 70);
+        control.set(54.6).unwrap();
+        assert_eq!(*level.lock().unwrap(), 55);
+    }
+
+    #[test]
+    fn falls_back_to_the_legacy_gnome_methods() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let level = Arc::new(Mutex::new(25));
+        let served = level.clone();
+        daemon.serve(GNOME_LEGACY_DESTINATION, &[GNOME_PATH], move |call| gnome_methods(call, &served));
+
+        let control = DbusBrightness::open(on(&daemon)).unwrap();
+        assert_eq!(control.api(), Api::GnomeMethods);
+        assert_eq!(control.current().unwrap(), 25.0);
+
+        control.set(60.0).unwrap();
+        assert_eq!(*level.lock().unwrap(), 60);
+    }
+
+    #[test]
+    fn drives_powerdevil_in_its_own_units() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let level = Arc::new(Mutex::new(500));
+        let served = level.clone();
+        daemon.serve(KDE_DESTINATION, &[KDE_PATH], move |call| powerdevil(call, &served));
+
+        let control = DbusBrightness::open(on(&daemon)).unwrap();
+        assert_eq!(control.api(), Api::PowerDevil);
+        assert_eq!(control.max().unwrap(), 1000.0);
+        assert_eq!(control.percent().unwrap(), 50.0);
+
+        control.set_percent(25.0).unwrap();
+        assert_eq!(*level.lock().unwrap(), 250);
+    }
+
+    #[test]
+    fn skips_a_gnome_daemon_without_a_backlight() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let none = Arc::new(Mutex::new(-1));
+        daemon.serve(GNOME_DESTINATION, &[GNOME_PATH], move |call| gnome_property(call, &none));
+        let level = Arc::new(Mutex::new(300));
+        daemon.serve(KDE_DESTINATION, &[KDE_PATH], move |call| powerdevil(call, &level));
+
+        let control = DbusBrightness::open(on(&daemon)).unwrap();
+        assert_eq!(control.api(), Api::PowerDevil);
+    }
+
+    #[test]
+    fn finds_a_daemon_under_another_name() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let level = Arc::new(Mutex::new(80));
+        let served = level.clone();
+        daemon.serve("org.example.Power", &["/org/example/Power"], move |call| {
+            gnome_property(call, &served)
+        });
+
+        let options = on(&daemon)
+            .destination(Some("org.example.Power".to_string()))
+            .path(Some("/org/example/Power".to_string()));
+        let control = DbusBrightness::open(options).unwrap();
+        assert_eq!(control.api(), Api::GnomeProperty);
+        assert_eq!(control.current().unwrap(), 80.0);
+    }
+
+    #[test]
+    fn reports_no_service_on_an_empty_bus() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
         };
-        let rpc = rpc.append1(value as u32);
-        self.send(rpc).map(|_| ())
+        assert!(DbusBrightness::open(on(&daemon)).is_err());
     }
 }
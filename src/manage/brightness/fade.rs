@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Milliseconds between steps when a fade doesn't ask for a step count.
+pub const DEFAULT_STEP_MS: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps progress `t` in `[0, 1]` to eased progress in `[0, 1]`.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease-in" => Ok(Easing::EaseIn),
+            "ease-out" => Ok(Easing::EaseOut),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fade {
+    pub duration: Duration,
+    pub steps: u32,
+    pub easing: Easing,
+}
+
+impl Fade {
+    /// A fade lasting `ms` milliseconds with a step every `DEFAULT_STEP_MS`.
+    pub fn new(ms: u64) -> Self {
+        Fade {
+            duration: Duration::from_millis(ms),
+            steps: (ms / DEFAULT_STEP_MS).max(1) as u32,
+            easing: Easing::EaseInOut,
+        }
+    }
+
+    pub fn steps(mut self, steps: u32) -> Self {
+        self.steps = steps.max(1);
+        self
+    }
+
+    pub fn easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    /// How long to wait between steps.
+    pub fn interval(&self) -> Duration {
+        self.duration / self.steps
+    }
+
+    /// The value to set at `step` (counting from 1) of a fade from `start`
+    /// to `target`.
+    pub fn value_at(&self, start: f64, target: f64, step: u32) -> f64 {
+        let t = step as f64 / self.steps as f64;
+        start + (target - start) * self.easing.apply(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 4] = [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut];
+
+    #[test]
+    fn easings_start_at_zero_and_end_at_one() {
+        for easing in &EASINGS {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn easings_never_move_backwards() {
+        for easing in &EASINGS {
+            let mut last = 0.0;
+            for i in 1..101 {
+                let eased = easing.apply(i as f64 / 100.0);
+                assert!(eased >= last, "{:?} went back at {}", easing, i);
+                last = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn easings_shape_the_middle() {
+        assert_eq!(Easing::Linear.apply(0.5), 0.5);
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::EaseInOut.apply(0.25) < 0.25);
+    }
+
+    #[test]
+    fn parses_easing_names() {
+        assert_eq!("linear".parse(), Ok(Easing::Linear));
+        assert_eq!("ease-in".parse(), Ok(Easing::EaseIn));
+        assert_eq!("ease-out".parse(), Ok(Easing::EaseOut));
+        assert_eq!("ease-in-out".parse(), Ok(Easing::EaseInOut));
+        assert_eq!("bounce".parse::<Easing>(), Err(()));
+    }
+
+    #[test]
+    fn fades_end_on_the_target() {
+        let fade = Fade::new(160).easing(Easing::EaseIn);
+        assert_eq!(fade.steps, 10);
+        assert_eq!(fade.interval(), Duration::from_millis(16));
+        assert_eq!(fade.value_at(80.0, 20.0, fade.steps), 20.0);
+        assert_eq!(fade.value_at(20.0, 80.0, 5), 35.0);
+    }
+
+    #[test]
+    fn fades_take_at_least_one_step() {
+        assert_eq!(Fade::new(0).steps, 1);
+        assert_eq!(Fade::new(100).steps(0).steps, 1);
+    }
+}
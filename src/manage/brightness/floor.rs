@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use super::Brightness;
+
+/// Keeps brightness-down from turning the panel off.
+pub const DEFAULT_FLOOR: Floor = Floor::Percent(1.0);
+
+/// The lowest brightness to allow, either in the backend's own units or as
+/// a percentage of its range. The own units are what `brightness --raw get`
+/// prints: sysfs steps for the fs and logind backends, PowerDevil's steps
+/// for KDE, and percentages for xcb, gamma and GNOME.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Floor {
+    Absolute(f64),
+    Percent(f64),
+}
+
+impl Floor {
+    /// No floor at all, the backend's own minimum.
+    pub fn off() -> Self {
+        Floor::Absolute(0.0)
+    }
+
+    /// Whether the floor is below a backend's `max`, leaving some range to
+    /// move in.
+    pub fn fits(&self, max: f64) -> bool {
+        match *self {
+            Floor::Absolute(v) => v < max,
+            Floor::Percent(p) => p < 100f64,
+        }
+    }
+
+    fn value(&self, min: f64, max: f64) -> f64 {
+        let value = match *self {
+            Floor::Absolute(v) => v,
+            Floor::Percent(p) => (min + p / 100f64 * (max - min)).ceil(),
+        };
+        value.max(min).min(max)
+    }
+}
+
+impl FromStr for Floor {
+    type Err = ();
+
+    /// Parses `200` as an absolute floor and `5%` as a percentage.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let s = s.trim();
+        let (number, floor): (&str, fn(f64) -> Floor) = if s.ends_with('%') {
+            (s[..s.len() - 1].trim(), Floor::Percent)
+        } else {
+            (s, Floor::Absolute)
+        };
+        match number.parse::<f64>() {
+            Ok(v) if v >= 0f64 && v.is_finite() => Ok(floor(v)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Stops another `Brightness` at a `Floor`, so `set` and `change_n_clip`
+/// never go below it. `min` stays the backend's own, so percentages still
+/// span the whole range.
+pub struct Floored<'a, B: 'a + Brightness> {
+    inner: &'a B,
+    floor: Floor,
+}
+
+impl<'a, B: Brightness> Floored<'a, B> {
+    pub fn new(inner: &'a B, floor: Floor) -> Self {
+        Floored {
+            inner: inner,
+            floor: floor,
+        }
+    }
+}
+
+impl<'a, B: Brightness> Brightness for Floored<'a, B> {
+    type E = B::E;
+
+    fn max(&self) -> Result<f64, Self::E> {
+        self.inner.max()
+    }
+
+    fn min(&self) -> Result<f64, Self::E> {
+        self.inner.min()
+    }
+
+    fn current(&self) -> Result<f64, Self::E> {
+        self.inner.current()
+    }
+
+    fn set(&self, value: f64) -> Result<(), Self::E> {
+        let value = try!(self.clip(value));
+        self.inner.set(value)
+    }
+
+    /// Clamps `value` to `[floor, max]`.
+    fn clip(&self, value: f64) -> Result<f64, Self::E> {
+        let (min, max) = (try!(self.inner.min()), try!(self.inner.max()));
+        Ok(value.max(self.floor.value(min, max)).min(max))
+    }
+
+    /// Steps down no further than the floor. A panel already below it,
+    /// say after `--allow-off`, stays where it is rather than jumping up.
+    fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), Self::E> {
+        let (min, max) = (try!(self.inner.min()), try!(self.inner.max()));
+        let floor = self.floor.value(min, max);
+        self.inner.change_with(|current| {
+            let next = next(current).min(max);
+            if next < current {
+                next.max(floor.min(current))
+            } else {
+                next
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::FakeBrightness;
+
+    #[test]
+    fn parses_absolute_and_percent_floors() {
+        assert_eq!("200".parse(), Ok(Floor::Absolute(200.0)));
+        assert_eq!(" 5% ".parse(), Ok(Floor::Percent(5.0)));
+        assert_eq!("2.5 %".parse(), Ok(Floor::Percent(2.5)));
+    }
+
+    #[test]
+    fn rejects_bad_floors() {
+        assert!("".parse::<Floor>().is_err());
+        assert!("%".parse::<Floor>().is_err());
+        assert!("low".parse::<Floor>().is_err());
+        assert!("-5".parse::<Floor>().is_err());
+        assert!("-5%".parse::<Floor>().is_err());
+        assert!("NaN".parse::<Floor>().is_err());
+    }
+
+    #[test]
+    fn floors_must_leave_room_below_max() {
+        assert!(Floor::Absolute(50.0).fits(100.0));
+        assert!(!Floor::Absolute(200.0).fits(100.0));
+        assert!(!Floor::Absolute(100.0).fits(100.0));
+        assert!(Floor::Percent(99.0).fits(100.0));
+        assert!(!Floor::Percent(100.0).fits(100.0));
+        assert!(Floor::off().fits(1.0));
+    }
+
+    #[test]
+    fn percent_floors_round_up_to_whole_units() {
+        let backlight = FakeBrightness::new(0.0, 937.0, 500.0);
+        let floored = Floored::new(&backlight, Floor::Percent(1.0));
+        floored.set(0.0).unwrap();
+        assert_eq!(backlight.value.get(), 10.0);
+    }
+
+    #[test]
+    fn stops_set_and_steps_at_the_floor() {
+        let backlight = FakeBrightness::new(0.0, 1000.0, 300.0);
+        let floored = Floored::new(&backlight, Floor::Absolute(200.0));
+
+        floored.change_n_clip(-500.0).unwrap();
+        assert_eq!(backlight.value.get(), 200.0);
+        floored.set(5000.0).unwrap();
+        assert_eq!(backlight.value.get(), 1000.0);
+    }
+
+    #[test]
+    fn steps_below_the_floor_never_jump_up_to_it() {
+        let backlight = FakeBrightness::new(0.0, 1000.0, 50.0);
+        let floored = Floored::new(&backlight, Floor::Absolute(200.0));
+
+        floored.change_n_clip(-10.0).unwrap();
+        assert_eq!(backlight.value.get(), 50.0);
+        floored.change_n_clip(10.0).unwrap();
+        assert_eq!(backlight.value.get(), 60.0);
+    }
+
+    #[test]
+    fn keeps_the_backend_minimum_for_percentages() {
+        let backlight = FakeBrightness::new(0.0, 1000.0, 200.0);
+        let floored = Floored::new(&backlight, Floor::Absolute(200.0));
+
+        assert_eq!(floored.min().unwrap(), 0.0);
+        assert_eq!(floored.percent().unwrap(), 20.0);
+    }
+
+    #[test]
+    fn turning_the_floor_off_allows_zero() {
+        let backlight = FakeBrightness::new(0.0, 1000.0, 300.0);
+        Floored::new(&backlight, Floor::off()).set(0.0).unwrap();
+        assert_eq!(backlight.value.get(), 0.0);
+    }
+}
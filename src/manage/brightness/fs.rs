@@ -1,4 +1,5 @@
 use std::fs::{
+    self,
     File,
     OpenOptions,
 };
@@ -11,6 +12,7 @@ use std::io::{
     Write,
 };
 use std::io::Error as IoError;
+use std::time::Duration;
 use std::num::ParseFloatError;
 use std::error::Error as ErrorTrait;
 use std::fmt::{
@@ -20,19 +22,174 @@ use std::fmt::{
 use std::fmt::Error as FmtError;
 
 use super::Brightness;
+use super::logind::Logind;
+use super::dbus::{
+    DbusError,
+    DbusOptions,
+};
+use super::watch::Watcher;
+
+/// Class directories, relative to the sysfs root.
+pub const BACKLIGHT_DIR: &'static str = "class/backlight";
+pub const LEDS_DIR: &'static str = "class/leds";
+
+/// Backlight interface types, best first, in the order the kernel's
+/// sysfs-class-backlight documentation recommends picking them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BacklightType {
+    Firmware,
+    Platform,
+    Raw,
+    Unknown,
+}
+
+impl BacklightType {
+    fn parse(s: &str) -> Self {
+        match s {
+            "firmware" => BacklightType::Firmware,
+            "platform" => BacklightType::Platform,
+            "raw" => BacklightType::Raw,
+            _ => BacklightType::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BacklightType::Firmware => "firmware",
+            BacklightType::Platform => "platform",
+            BacklightType::Raw => "raw",
+            BacklightType::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    pub kind: BacklightType,
+    pub path: PathBuf,
+}
+
+/// Lists the backlight devices under `class_dir`, best ranked first.
+pub fn devices(class_dir: &Path) -> Result<Vec<Device>, Error> {
+    let mut devices = Vec::new();
+    for entry in try!(fs::read_dir(class_dir)) {
+        let path = try!(entry).path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => continue,
+        };
+
+        let mut kind = String::new();
+        let kind = match File::open(path.join("type")) {
+            Ok(mut f) => {
+                f.read_to_string(&mut kind).ok();
+                BacklightType::parse(kind.trim())
+            },
+            Err(_) => BacklightType::Unknown,
+        };
+
+        devices.push(Device {
+            name: name,
+            kind: kind,
+            path: path,
+        });
+    }
+    devices.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    Ok(devices)
+}
+
+/// Lists the keyboard backlights among the LEDs under `leds_dir`.
+pub fn kbd_devices(leds_dir: &Path) -> Result<Vec<Device>, Error> {
+    let devices = try!(devices(leds_dir));
+    Ok(devices.into_iter().filter(|d| d.name.ends_with("::kbd_backlight")).collect())
+}
 
 #[allow(dead_code)]
 pub struct FsBrightness {
+    dir: PathBuf,
     max_path: PathBuf,
     curr_path: PathBuf,
+    logind: Option<Logind>,
 }
 
 #[allow(dead_code)]
 impl FsBrightness {
     pub fn new(backlight_dir: &str) -> Self {
+        FsBrightness::at(Path::new(backlight_dir))
+    }
+
+    pub fn at(backlight_dir: &Path) -> Self {
         FsBrightness {
-            max_path: Path::new(backlight_dir).join("max_brightness").to_owned(),
-            curr_path: Path::new(backlight_dir).join("brightness").to_owned(),
+            dir: backlight_dir.to_owned(),
+            max_path: backlight_dir.join("max_brightness"),
+            curr_path: backlight_dir.join("brightness"),
+            logind: None,
+        }
+    }
+
+    /// The device's directory name, e.g. `intel_backlight`.
+    pub fn name(&self) -> Option<&str> {
+        self.dir.file_name().and_then(|n| n.to_str())
+    }
+
+    /// Watches for changes to the brightness, in the hardware's
+    /// `actual_brightness` when the device has one and in `brightness`,
+    /// which some firmware updates without notifying the other.
+    pub fn watch(&self, interval: Duration) -> Watcher {
+        let actual = self.dir.join("actual_brightness");
+        if actual.exists() {
+            Watcher::new(vec![actual, self.curr_path.clone()], interval)
+        } else {
+            Watcher::new(vec![self.curr_path.clone()], interval)
+        }
+    }
+
+    /// Keeps reading from sysfs but writes through systemd-logind on the
+    /// logind bus of `options`, with its timeout.
+    pub fn through_logind(mut self, options: &DbusOptions) -> Result<Self, Error> {
+        self.logind = Some(try!(Logind::new(options.logind_bus(), options.timeout_ms())));
+        Ok(self)
+    }
+
+    /// Whether `set` can write the sysfs `brightness` file directly.
+    pub fn writable(&self) -> bool {
+        OpenOptions::new().write(true).open(&self.curr_path).is_ok()
+    }
+
+    /// The device name and its class (`backlight`, `leds`), as logind
+    /// wants them.
+    fn logind_id(&self) -> Option<(&str, &str)> {
+        let name = self.name();
+        let subsystem = self.dir.parent()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str());
+        match (subsystem, name) {
+            (Some(s), Some(n)) => Some((s, n)),
+            _ => None,
+        }
+    }
+
+    /// Uses the device called `name` under `class/backlight` of the sysfs
+    /// tree at `root`, or the best ranked one if no name is given.
+    pub fn find(root: &Path, name: Option<&str>) -> Result<Self, Error> {
+        FsBrightness::pick(try!(devices(&root.join(BACKLIGHT_DIR))), name)
+    }
+
+    /// Uses the keyboard backlight called `name` under `class/leds` of the
+    /// sysfs tree at `root`, or the first one if no name is given.
+    pub fn find_kbd(root: &Path, name: Option<&str>) -> Result<Self, Error> {
+        FsBrightness::pick(try!(kbd_devices(&root.join(LEDS_DIR))), name)
+    }
+
+    fn pick(devices: Vec<Device>, name: Option<&str>) -> Result<Self, Error> {
+        let device = match name {
+            Some(n) => devices.into_iter().find(|d| d.name == n),
+            None => devices.into_iter().next(),
+        };
+        match device {
+            Some(d) => Ok(FsBrightness::at(&d.path)),
+            None => Err(Error::NoDevice),
         }
     }
 }
@@ -71,10 +228,16 @@ impl Brightness for FsBrightness {
             return Err(Error::OutOfRange);
         }
 
+        if let Some(ref logind) = self.logind {
+            let (subsystem, name) = try!(self.logind_id().ok_or(Error::NoDevice));
+            try!(logind.set_brightness(subsystem, name, value.round() as u32));
+            return Ok(());
+        }
+
         let mut control = try!(OpenOptions::new()
             .write(true)
             .open(&self.curr_path));
-        try!(control.write_fmt(format_args!("{}", value as u32)));
+        try!(control.write_fmt(format_args!("{}", value.round() as u32)));
         Ok(())
     }
 }
@@ -86,6 +249,13 @@ impl From<IoError> for Error {
     }
 }
 
+impl From<DbusError> for Error {
+    #[inline]
+    fn from(err: DbusError) -> Self {
+        Error::Dbus(err)
+    }
+}
+
 impl From<ParseFloatError> for Error {
     #[inline]
     fn from(err: ParseFloatError) -> Self {
@@ -97,7 +267,9 @@ impl From<ParseFloatError> for Error {
 pub enum Error {
     Io(IoError),
     Parse(ParseFloatError),
+    Dbus(DbusError),
     OutOfRange,
+    NoDevice,
 }
 
 impl ErrorTrait for Error {
@@ -105,7 +277,9 @@ impl ErrorTrait for Error {
         match *self {
             Error::Io(ref io) => io.description(),
             Error::Parse(ref p) => p.description(),
+            Error::Dbus(ref d) => d.description(),
             Error::OutOfRange => "Brightness value out of range",
+            Error::NoDevice => "No backlight device found",
         }
     }
 
@@ -113,7 +287,9 @@ impl ErrorTrait for Error {
         match *self {
             Error::Io(ref io) => Some(io),
             Error::Parse(ref p) => Some(p),
+            Error::Dbus(ref d) => Some(d),
             Error::OutOfRange => None,
+            Error::NoDevice => None,
         }
     }
 }
@@ -123,3 +299,136 @@ impl Display for Error {
         fmt.write_str(self.description())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use super::*;
+    use super::super::dbus::Bus;
+    use testing::{
+        scratch_dir,
+        write,
+        DbusDaemon,
+    };
+
+    fn backlight(class_dir: &Path, name: &str, kind: Option<&str>) {
+        let dir = class_dir.join(name);
+        write(&dir.join("max_brightness"), "100\n");
+        write(&dir.join("brightness"), "50\n");
+        if let Some(kind) = kind {
+            write(&dir.join("type"), &format!("{}\n", kind));
+        }
+    }
+
+    #[test]
+    fn devices_rank_firmware_then_platform_then_raw() {
+        let root = scratch_dir("fs-rank");
+        let class_dir = root.join(BACKLIGHT_DIR);
+        backlight(&class_dir, "a", Some("raw"));
+        backlight(&class_dir, "b", Some("firmware"));
+        backlight(&class_dir, "c", Some("platform"));
+        backlight(&class_dir, "d", None);
+
+        let found = devices(&class_dir).unwrap();
+        let ranked: Vec<_> = found.iter().map(|d| (&d.name as &str, d.kind)).collect();
+        assert_eq!(ranked, vec![
+            ("b", BacklightType::Firmware),
+            ("c", BacklightType::Platform),
+            ("a", BacklightType::Raw),
+            ("d", BacklightType::Unknown),
+        ]);
+        assert_eq!(found[0].path, class_dir.join("b"));
+    }
+
+    #[test]
+    fn devices_of_one_type_sort_by_name() {
+        let root = scratch_dir("fs-names");
+        let class_dir = root.join(BACKLIGHT_DIR);
+        backlight(&class_dir, "b", Some("raw"));
+        backlight(&class_dir, "a", Some("raw"));
+
+        let names: Vec<_> = devices(&class_dir).unwrap().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn kbd_devices_keep_only_keyboard_backlights() {
+        let root = scratch_dir("fs-kbd");
+        let leds_dir = root.join(LEDS_DIR);
+        backlight(&leds_dir, "input3::capslock", None);
+        backlight(&leds_dir, "tpacpi::kbd_backlight", None);
+
+        let names: Vec<_> = kbd_devices(&leds_dir).unwrap().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, vec!["tpacpi::kbd_backlight"]);
+    }
+
+    #[test]
+    fn reads_and_writes_the_brightness_files() {
+        let root = scratch_dir("fs-rw");
+        let class_dir = root.join(BACKLIGHT_DIR);
+        backlight(&class_dir, "acpi_video0", Some("firmware"));
+
+        let control = FsBrightness::at(&class_dir.join("acpi_video0"));
+        assert_eq!(control.name(), Some("acpi_video0"));
+        assert_eq!(control.max().unwrap(), 100.0);
+        assert_eq!(control.current().unwrap(), 50.0);
+
+        control.set(75.0).unwrap();
+        assert_eq!(control.current().unwrap(), 75.0);
+        control.set(40.6).unwrap();
+        assert_eq!(control.current().unwrap(), 41.0);
+        assert!(control.set(101.0).is_err());
+    }
+
+    #[test]
+    fn finds_devices_under_the_given_root() {
+        let root = scratch_dir("fs-find");
+        backlight(&root.join(BACKLIGHT_DIR), "intel_backlight", Some("raw"));
+        backlight(&root.join(BACKLIGHT_DIR), "acpi_video0", Some("firmware"));
+        backlight(&root.join(LEDS_DIR), "tpacpi::kbd_backlight", None);
+
+        let best = FsBrightness::find(&root, None).unwrap();
+        assert_eq!(best.name(), Some("acpi_video0"));
+        let named = FsBrightness::find(&root, Some("intel_backlight")).unwrap();
+        assert_eq!(named.name(), Some("intel_backlight"));
+        assert!(FsBrightness::find(&root, Some("nowhere")).is_err());
+
+        let kbd = FsBrightness::find_kbd(&root, None).unwrap();
+        assert_eq!(kbd.name(), Some("tpacpi::kbd_backlight"));
+        let empty = scratch_dir("fs-find-empty");
+        assert!(FsBrightness::find_kbd(&empty, None).is_err());
+    }
+
+    #[test]
+    fn writes_through_logind_by_class_and_name() {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return,
+        };
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let served = calls.clone();
+        daemon.serve("org.freedesktop.login1", &["/org/freedesktop/login1/session/auto"], move |call| {
+            if let (Some(subsystem), Some(name), Some(value)) = call.get3::<&str, &str, u32>() {
+                served.lock().unwrap().push((subsystem.to_string(), name.to_string(), value));
+            }
+            call.method_return()
+        });
+
+        let root = scratch_dir("fs-logind");
+        let class_dir = root.join(BACKLIGHT_DIR);
+        backlight(&class_dir, "intel_backlight", Some("raw"));
+        let control = FsBrightness::at(&class_dir.join("intel_backlight"))
+            .through_logind(&DbusOptions::default()
+                            .logind(Bus::Address(daemon.address().to_string())))
+            .unwrap();
+
+        control.set(29.7).unwrap();
+        assert_eq!(*calls.lock().unwrap(),
+                   vec![("backlight".to_string(), "intel_backlight".to_string(), 30)]);
+        assert_eq!(control.current().unwrap(), 50.0);
+    }
+}
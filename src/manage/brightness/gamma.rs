@@ -0,0 +1,450 @@
+extern crate xcb as xcb_ffi;
+
+use self::xcb_ffi::base::Connection;
+use self::xcb_ffi::randr;
+use self::xcb_ffi::xproto;
+use self::xcb_ffi::xproto::{
+    Atom,
+    Window,
+};
+use self::xcb_ffi::ffi::xproto as xproto_ffi;
+
+use manage::color::temperature_levels;
+
+use super::Brightness;
+use super::perceptual::Curve;
+use super::xcb::{
+    Error,
+    open_randr,
+};
+
+/// How a typical screen turns gamma ramp levels into light. Scaling a ramp
+/// already dims on a roughly perceptual scale; this converts to the linear
+/// light a backlight gives, to dim both kinds of output alike.
+pub const DISPLAY_GAMMA: f64 = 2.2;
+
+/// Red, green and blue ramps.
+type Ramp = [Vec<u16>; 3];
+
+/// A CRTC and the names of the outputs it drives.
+#[derive(Debug)]
+pub struct Crtc {
+    id: randr::Crtc,
+    root: Window,
+    size: u16,
+    outputs: Vec<String>,
+}
+
+impl Crtc {
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+}
+
+/// Gamma ramps of the active CRTCs on a connection.
+///
+/// Each ramp is the CRTC's original ramp scaled per channel, and the level
+/// of a channel is how far it has been scaled. Brightness is the brightest
+/// channel's level and the ratios between channels are left for colour
+/// temperature, which lets the two be changed independently from separate
+/// runs. The original ramp, with whatever calibration it carries, is kept
+/// in a root window property from the first change until the ramps are
+/// back to it, so it lasts exactly as long as the X server.
+pub struct Gamma<'a> {
+    connection: &'a Connection,
+    crtcs: Vec<Crtc>,
+}
+
+impl<'a> Gamma<'a> {
+    /// Finds the active CRTCs on `roots`, or only the one driving `output`.
+    pub fn new(connection: &'a Connection, roots: &[Window], output: Option<&str>)
+        -> Result<Self, Error>
+    {
+        let crtcs = try!(find_crtcs(connection, roots, |outputs| {
+            output.map(|name| outputs.iter().any(|o| o == name)).unwrap_or(true)
+        }));
+
+        if crtcs.is_empty() {
+            return Err(match output {
+                Some(name) => Error::NoSuchOutput(name.to_string()),
+                None => Error::NoCrtc,
+            });
+        }
+
+        Ok(Gamma {
+            connection: connection,
+            crtcs: crtcs,
+        })
+    }
+
+    /// Finds the active CRTCs on `roots` that drive none of the outputs in
+    /// `skip`.
+    pub fn without(connection: &'a Connection, roots: &[Window], skip: &[&str])
+        -> Result<Self, Error>
+    {
+        let crtcs = try!(find_crtcs(connection, roots, |outputs| {
+            !outputs.iter().any(|o| skip.contains(&(o as &str)))
+        }));
+
+        if crtcs.is_empty() {
+            return Err(Error::NoCrtc);
+        }
+
+        Ok(Gamma {
+            connection: connection,
+            crtcs: crtcs,
+        })
+    }
+
+    pub fn crtcs(&self) -> &[Crtc] {
+        &self.crtcs
+    }
+
+    /// The red, green and blue levels of `crtc`, from 0 to 1.
+    pub fn levels(&self, crtc: &Crtc) -> Result<[f64; 3], Error> {
+        let current = try!(self.ramp(crtc));
+        let original = try!(self.original(crtc)).unwrap_or_else(|| current.clone());
+
+        let mut levels = [1.0; 3];
+        for c in 0..3 {
+            let top = |channel: &[u16]| channel.last().map(|v| *v as f64).unwrap_or(0.0);
+            if top(&original[c]) > 0.0 {
+                levels[c] = (top(&current[c]) / top(&original[c])).min(1.0);
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Writes `crtc`'s original ramps scaled to `levels`, saving the
+    /// original first if this is the first change since it was last
+    /// restored.
+    pub fn set_levels(&self, crtc: &Crtc, levels: [f64; 3]) -> Result<(), Error> {
+        let original = match try!(self.original(crtc)) {
+            Some(o) => o,
+            None => {
+                let current = try!(self.ramp(crtc));
+                try!(self.save_original(crtc, &current));
+                current
+            },
+        };
+
+        let scale = |channel: &[u16], level: f64| -> Vec<u16> {
+            let level = level.max(0.0).min(1.0);
+            channel.iter().map(|v| (*v as f64 * level).round() as u16).collect()
+        };
+        try!(randr::set_crtc_gamma_checked(self.connection, crtc.id,
+                                           &scale(&original[0], levels[0]),
+                                           &scale(&original[1], levels[1]),
+                                           &scale(&original[2], levels[2]))
+            .request_check()
+            .map_err(|_| Error::GammaQuery));
+
+        if levels.iter().all(|l| *l >= 1.0) {
+            try!(self.forget_original(crtc));
+        }
+        Ok(())
+    }
+
+    /// Brightness of `crtc` from 0 to 1, its brightest channel.
+    pub fn brightness_of(&self, crtc: &Crtc) -> Result<f64, Error> {
+        let levels = try!(self.levels(crtc));
+        Ok(levels[0].max(levels[1]).max(levels[2]))
+    }
+
+    /// Scales every CRTC to `brightness` from 0 to 1.
+    pub fn set_brightness(&self, brightness: f64) -> Result<(), Error> {
+        for crtc in &self.crtcs {
+            try!(self.set_brightness_of(crtc, brightness));
+        }
+        Ok(())
+    }
+
+    /// Scales `crtc` to `brightness` from 0 to 1, keeping the balance
+    /// between its channels.
+    pub fn set_brightness_of(&self, crtc: &Crtc, brightness: f64) -> Result<(), Error> {
+        let levels = try!(self.levels(crtc));
+        let top = levels[0].max(levels[1]).max(levels[2]);
+        let levels = if top > 0.0 {
+            [levels[0] / top, levels[1] / top, levels[2] / top]
+        } else {
+            [1.0, 1.0, 1.0]
+        };
+        self.set_levels(crtc, [levels[0] * brightness,
+                               levels[1] * brightness,
+                               levels[2] * brightness])
+    }
+
+    /// Tints every CRTC to a blackbody at `kelvin`, keeping its brightness.
+    /// `NEUTRAL` puts back the original ramp, still scaled if the CRTC has
+    /// been dimmed.
+    pub fn set_temperature(&self, kelvin: f64) -> Result<(), Error> {
+        let tint = temperature_levels(kelvin);
+        for crtc in &self.crtcs {
+            let brightness = try!(self.brightness_of(crtc));
+            try!(self.set_levels(crtc, [tint[0] * brightness,
+                                        tint[1] * brightness,
+                                        tint[2] * brightness]));
+        }
+        Ok(())
+    }
+
+    fn ramp(&self, crtc: &Crtc) -> Result<Ramp, Error> {
+        let ramp = try!(randr::get_crtc_gamma(self.connection, crtc.id)
+            .get_reply()
+            .map_err(|_| Error::GammaQuery));
+        Ok([ramp.red().to_vec(), ramp.green().to_vec(), ramp.blue().to_vec()])
+    }
+
+    /// The root window property holding `crtc`'s original ramp.
+    fn original_atom(&self, crtc: &Crtc, only_if_exists: bool) -> Result<Atom, Error> {
+        let name = format!("_SYSHOOKS_ORIGINAL_GAMMA_{}", crtc.id);
+        xproto::intern_atom(self.connection, only_if_exists, &name).get_reply()
+            .map(|reply| reply.atom())
+            .map_err(|_| Error::GammaQuery)
+    }
+
+    /// `crtc`'s original ramp, if it has been saved.
+    fn original(&self, crtc: &Crtc) -> Result<Option<Ramp>, Error> {
+        let atom = try!(self.original_atom(crtc, true));
+        if atom == xproto_ffi::XCB_ATOM_NONE {
+            return Ok(None);
+        }
+        let property = try!(xproto::get_property(self.connection, false, crtc.root, atom,
+                                                 xproto_ffi::XCB_ATOM_CARDINAL,
+                                                 0, 3 * crtc.size as u32)
+            .get_reply()
+            .map_err(|_| Error::GammaQuery));
+
+        let values: &[u16] = property.value();
+        let size = crtc.size as usize;
+        if property.format() != 16 || values.len() != 3 * size {
+            return Ok(None);
+        }
+        Ok(Some([values[..size].to_vec(),
+                 values[size..2 * size].to_vec(),
+                 values[2 * size..].to_vec()]))
+    }
+
+    fn save_original(&self, crtc: &Crtc, ramp: &Ramp) -> Result<(), Error> {
+        let atom = try!(self.original_atom(crtc, false));
+        let values: Vec<u16> = ramp.iter().flat_map(|c| c.iter().cloned()).collect();
+        xproto::change_property_checked(self.connection, xproto_ffi::XCB_PROP_MODE_REPLACE as u8,
+                                        crtc.root, atom, xproto_ffi::XCB_ATOM_CARDINAL,
+                                        16, &values)
+            .request_check()
+            .map_err(|_| Error::GammaQuery)
+    }
+
+    fn forget_original(&self, crtc: &Crtc) -> Result<(), Error> {
+        let atom = try!(self.original_atom(crtc, true));
+        if atom == xproto_ffi::XCB_ATOM_NONE {
+            return Ok(());
+        }
+        xproto::delete_property_checked(self.connection, crtc.root, atom)
+            .request_check()
+            .map_err(|_| Error::GammaQuery)
+    }
+}
+
+/// The active CRTCs on `roots` with a usable gamma ramp whose outputs
+/// `wanted` accepts.
+fn find_crtcs<F>(connection: &Connection, roots: &[Window], wanted: F)
+    -> Result<Vec<Crtc>, Error>
+    where F: Fn(&[String]) -> bool
+{
+    let mut crtcs = Vec::new();
+
+    for root in roots {
+        let resources = try!(randr::get_screen_resources_current(connection, *root)
+            .get_reply()
+            .map_err(|_| Error::NoRandr));
+        let config_timestamp = resources.config_timestamp();
+
+        for id in resources.crtcs() {
+            let info = match randr::get_crtc_info(connection, *id, config_timestamp)
+                .get_reply() {
+                Ok(i) => i,
+                Err(_) => continue,
+            };
+            if info.mode() == 0 || info.num_outputs() == 0 {
+                continue;
+            }
+
+            let mut outputs = Vec::new();
+            for o in info.outputs() {
+                if let Ok(info) = randr::get_output_info(connection, *o, config_timestamp)
+                    .get_reply() {
+                    outputs.push(String::from_utf8_lossy(info.name()).into_owned());
+                }
+            }
+            if !wanted(&outputs) {
+                continue;
+            }
+
+            let size = try!(randr::get_crtc_gamma_size(connection, *id)
+                .get_reply()
+                .map_err(|_| Error::GammaQuery))
+                .size();
+            if size < 2 {
+                continue;
+            }
+
+            crtcs.push(Crtc {
+                id: *id,
+                root: *root,
+                size: size,
+                outputs: outputs,
+            });
+        }
+    }
+
+    Ok(crtcs)
+}
+
+/// The ramp level that gives `light`, from 0 to 1, on a typical screen.
+pub fn level_for_light(light: f64) -> f64 {
+    Curve::new(DISPLAY_GAMMA).to_perceived(light)
+}
+
+/// The light, from 0 to 1, that a ramp at `level` gives on a typical screen.
+pub fn light_for_level(level: f64) -> f64 {
+    Curve::new(DISPLAY_GAMMA).to_linear(level)
+}
+
+/// Dims outputs in software through their CRTC gamma ramps, for screens
+/// with no backlight RandR can control.
+pub struct GammaBrightness {
+    connection: Connection,
+    roots: Vec<Window>,
+    output: Option<String>,
+}
+
+impl GammaBrightness {
+    /// Connects to the X server, controlling only `output` if given.
+    pub fn connect(output: Option<&str>) -> Result<Self, Error> {
+        let (connection, roots) = try!(open_randr(None));
+        let control = GammaBrightness {
+            connection: connection,
+            roots: roots,
+            output: output.map(|o| o.to_string()),
+        };
+        try!(control.gamma());
+        Ok(control)
+    }
+
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_ref().map(|o| o as &str)
+    }
+
+    pub fn gamma<'a>(&'a self) -> Result<Gamma<'a>, Error> {
+        Gamma::new(&self.connection, &self.roots, self.output())
+    }
+}
+
+impl Brightness for GammaBrightness {
+    type E = Error;
+
+    fn max(&self) -> Result<f64, Error> {
+        Ok(100f64)
+    }
+
+    fn min(&self) -> Result<f64, Error> {
+        Ok(0f64)
+    }
+
+    /// Reports the first CRTC.
+    fn current(&self) -> Result<f64, Error> {
+        let gamma = try!(self.gamma());
+        Ok(100f64 * try!(gamma.brightness_of(&gamma.crtcs()[0])))
+    }
+
+    fn set(&self, value: f64) -> Result<(), Error> {
+        try!(self.gamma()).set_brightness(value / 100f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use manage::color::NEUTRAL;
+    use testing::Xvfb;
+
+    /// A connection to `xvfb`, which must drive a CRTC with a gamma ramp.
+    fn gamma_on(xvfb: &Xvfb) -> (Connection, Vec<Window>) {
+        let (connection, roots) = open_randr(Some(xvfb.display())).unwrap();
+        if let Err(e) = Gamma::new(&connection, &roots, None) {
+            panic!("Xvfb has no CRTC gamma ramp: {}", e);
+        }
+        (connection, roots)
+    }
+
+    #[test]
+    fn light_and_level_round_trip() {
+        for light in &[0.0, 0.1, 0.5, 1.0] {
+            assert!((light_for_level(level_for_light(*light)) - light).abs() < 1e-9);
+        }
+        assert!(level_for_light(0.5) > 0.5);
+    }
+
+    #[test]
+    #[ignore]
+    fn scales_and_restores_the_original_ramp() {
+        let xvfb = Xvfb::start();
+        let (connection, roots) = gamma_on(&xvfb);
+        let gamma = Gamma::new(&connection, &roots, None).unwrap();
+        let crtc = &gamma.crtcs()[0];
+
+        // A calibrated ramp that tops out below full scale.
+        let size = crtc.size as usize;
+        let custom: Vec<u16> = (0..size).map(|i| (i * 40000 / (size - 1)) as u16).collect();
+        randr::set_crtc_gamma_checked(&connection, crtc.id, &custom, &custom, &custom)
+            .request_check().unwrap();
+        assert_eq!(gamma.brightness_of(crtc).unwrap(), 1.0);
+
+        gamma.set_brightness(0.5).unwrap();
+        assert_eq!(gamma.ramp(crtc).unwrap()[1].last(), Some(&20000));
+        assert!((gamma.brightness_of(crtc).unwrap() - 0.5).abs() < 1e-3);
+        assert!(gamma.original(crtc).unwrap().is_some());
+
+        gamma.set_temperature(3500.0).unwrap();
+        assert!((gamma.brightness_of(crtc).unwrap() - 0.5).abs() < 1e-3);
+
+        gamma.set_brightness(1.0).unwrap();
+        gamma.set_temperature(NEUTRAL).unwrap();
+        assert_eq!(gamma.ramp(crtc).unwrap(), [custom.clone(), custom.clone(), custom]);
+        assert!(gamma.original(crtc).unwrap().is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn reset_keeps_the_calibration_of_a_dimmed_ramp() {
+        let xvfb = Xvfb::start();
+        let (connection, roots) = gamma_on(&xvfb);
+        let gamma = Gamma::new(&connection, &roots, None).unwrap();
+        let crtc = &gamma.crtcs()[0];
+
+        // A curved calibration, which a linear ramp would lose.
+        let size = crtc.size as usize;
+        let custom: Vec<u16> = (0..size)
+            .map(|i| (65535.0 * (i as f64 / (size - 1) as f64).powf(1.5)).round() as u16)
+            .collect();
+        randr::set_crtc_gamma_checked(&connection, crtc.id, &custom, &custom, &custom)
+            .request_check().unwrap();
+
+        gamma.set_brightness(0.5).unwrap();
+        gamma.set_temperature(2700.0).unwrap();
+        gamma.set_temperature(NEUTRAL).unwrap();
+
+        // Levels are read back from 16 bit values, so allow a step of
+        // rounding.
+        let ramp = gamma.ramp(crtc).unwrap();
+        for channel in ramp.iter() {
+            for (v, c) in channel.iter().zip(custom.iter()) {
+                assert!((*v as f64 - *c as f64 * 0.5).abs() <= 1.0);
+            }
+        }
+
+        gamma.set_brightness(1.0).unwrap();
+        assert_eq!(gamma.ramp(crtc).unwrap(), [custom.clone(), custom.clone(), custom]);
+    }
+}
@@ -0,0 +1,44 @@
+extern crate dbus;
+
+use self::dbus::{Connection, Message};
+use self::dbus::Error as DbusError;
+
+use super::dbus::{
+    Bus,
+    connect,
+};
+
+/// Writes brightness through systemd-logind, which lets the user owning the
+/// active session change backlights and LEDs without write access to sysfs.
+pub struct Logind {
+    connection: Connection,
+    timeout_ms: i32,
+}
+
+impl Logind {
+    /// Connects to logind on `bus`, normally the system bus, waiting up to
+    /// `timeout_ms` for each reply.
+    pub fn new(bus: &Bus, timeout_ms: i32) -> Result<Self, DbusError> {
+        Ok(Logind {
+            connection: try!(connect(bus)),
+            timeout_ms: timeout_ms,
+        })
+    }
+
+    /// Calls `org.freedesktop.login1.Session.SetBrightness` on the caller's
+    /// session. `subsystem` is `backlight` or `leds`, `name` the device.
+    pub fn set_brightness(&self, subsystem: &str, name: &str, value: u32)
+        -> Result<(), DbusError>
+    {
+        let rpc = match Message::new_method_call(
+                "org.freedesktop.login1",
+                "/org/freedesktop/login1/session/auto",
+                "org.freedesktop.login1.Session",
+                "SetBrightness") {
+            Ok(m) => m,
+            Err(s) => return Err(DbusError::new_custom("CREATE_MESSAGE", &s)),
+        };
+        let rpc = rpc.append3(subsystem, name, value);
+        self.connection.send_with_reply_and_block(rpc, self.timeout_ms).map(|_| ())
+    }
+}
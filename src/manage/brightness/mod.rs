@@ -1,9 +1,23 @@
 pub mod fs;
 pub mod dbus;
+pub mod logind;
 pub mod xcb;
+pub mod gamma;
+pub mod probe;
+pub mod fade;
+pub mod perceptual;
+pub mod floor;
+pub mod auto;
+pub mod watch;
 
 use std::error::Error;
+use std::thread::sleep;
 
+use self::fade::Fade;
+
+/// A brightness control. `max`, `min`, `current` and `set` work in the
+/// backend's own units; `percent` and `set_percent` give every backend the
+/// same 0 - 100 scale.
 pub trait Brightness {
     type E: Error;
 
@@ -12,18 +26,58 @@ pub trait Brightness {
     fn current(&self) -> Result<f64, Self::E>;
     fn set(&self, value: f64) -> Result<(), Self::E>;
 
-    fn change_n_clip(&self, delta: f64) -> Result<(), Self::E> {
+    /// The current value as a percentage of `[min, max]`.
+    fn percent(&self) -> Result<f64, Self::E> {
+        let (min, max) = (try!(self.min()), try!(self.max()));
+        Ok(100f64 * (try!(self.current()) - min) / (max - min))
+    }
+
+    /// Sets the value to `percent` of the way from `min` to `max`.
+    fn set_percent(&self, percent: f64) -> Result<(), Self::E> {
         let (min, max) = (try!(self.min()), try!(self.max()));
+        let percent = percent.max(0.0).min(100.0);
+        self.set(min + percent / 100f64 * (max - min))
+    }
+
+    fn change_n_clip(&self, delta: f64) -> Result<(), Self::E> {
+        self.change_with(|current| current + delta)
+    }
+
+    /// Moves to `next(current)`, clipped to `[min, max]`. Backends that
+    /// drive several outputs apply `next` to each output's own value, and
+    /// adapters translate it into their inner backend's units.
+    fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), Self::E> {
         let current = try!(self.current());
+        let next = try!(self.clip(next(current)));
 
-        let next = if current + delta > max {
+        self.set(next)
+    }
+
+    /// Clamps `value` to `[min, max]`.
+    fn clip(&self, value: f64) -> Result<f64, Self::E> {
+        let (min, max) = (try!(self.min()), try!(self.max()));
+
+        Ok(if value > max {
             max
-        } else if current + delta < min {
+        } else if value < min {
             min
         } else {
-            current + delta
-        };
+            value
+        })
+    }
 
-        self.set(next)
+    /// Moves gradually from the current value to `target`, blocking until
+    /// the fade is done.
+    fn fade_to(&self, target: f64, fade: &Fade) -> Result<(), Self::E> {
+        let start = try!(self.current());
+        let target = try!(self.clip(target));
+
+        for step in 1..fade.steps + 1 {
+            try!(self.set(fade.value_at(start, target, step)));
+            if step < fade.steps {
+                sleep(fade.interval());
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use super::Brightness;
+
+pub const DEFAULT_EXPONENT: f64 = 2.2;
+
+/// A gamma curve between linear backend values and perceived brightness,
+/// both as fractions in `[0, 1]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Curve {
+    exponent: f64,
+}
+
+impl Curve {
+    pub fn new(exponent: f64) -> Self {
+        Curve {
+            exponent: exponent,
+        }
+    }
+
+    /// A curve that leaves values untouched.
+    pub fn linear() -> Self {
+        Curve::new(1.0)
+    }
+
+    pub fn to_perceived(&self, linear: f64) -> f64 {
+        linear.max(0.0).min(1.0).powf(1.0 / self.exponent)
+    }
+
+    pub fn to_linear(&self, perceived: f64) -> f64 {
+        perceived.max(0.0).min(1.0).powf(self.exponent)
+    }
+}
+
+impl FromStr for Curve {
+    type Err = ();
+
+    /// Parses an exponent, which has to be positive for the curve to map
+    /// `[0, 1]` onto itself.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.trim().parse::<f64>() {
+            Ok(exponent) if exponent > 0.0 && exponent.is_finite() => Ok(Curve::new(exponent)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::new(DEFAULT_EXPONENT)
+    }
+}
+
+/// Presents another `Brightness` as a 0 - 100 perceived scale, so equal
+/// steps look equally large. With `Curve::linear` it is just the other
+/// backend's `percent`.
+pub struct Perceptual<'a, B: 'a + Brightness> {
+    inner: &'a B,
+    curve: Curve,
+}
+
+impl<'a, B: Brightness> Perceptual<'a, B> {
+    pub fn new(inner: &'a B, curve: Curve) -> Self {
+        Perceptual {
+            inner: inner,
+            curve: curve,
+        }
+    }
+}
+
+impl<'a, B: Brightness> Brightness for Perceptual<'a, B> {
+    type E = B::E;
+
+    fn max(&self) -> Result<f64, Self::E> {
+        Ok(100f64)
+    }
+
+    fn min(&self) -> Result<f64, Self::E> {
+        Ok(0f64)
+    }
+
+    fn current(&self) -> Result<f64, Self::E> {
+        let linear = try!(self.inner.percent()) / 100f64;
+        Ok(100f64 * self.curve.to_perceived(linear))
+    }
+
+    fn set(&self, value: f64) -> Result<(), Self::E> {
+        let linear = self.curve.to_linear(value / 100f64);
+        self.inner.set_percent(100f64 * linear)
+    }
+
+    /// Hands the step to the inner backend, so each output it drives moves
+    /// by the same perceived amount from its own level.
+    fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), Self::E> {
+        let (min, max) = (try!(self.inner.min()), try!(self.inner.max()));
+        let curve = self.curve;
+        self.inner.change_with(|value| {
+            let perceived = 100f64 * curve.to_perceived((value - min) / (max - min));
+            let linear = curve.to_linear(next(perceived) / 100f64);
+            min + linear * (max - min)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use super::super::Brightness;
+    use testing::NoError;
+
+    /// Two outputs on a 0 - 100 scale, like `XcbBrightness` with two
+    /// backlit outputs.
+    struct TwoOutputs([Cell<f64>; 2]);
+
+    impl Brightness for TwoOutputs {
+        type E = NoError;
+
+        fn max(&self) -> Result<f64, NoError> {
+            Ok(100.0)
+        }
+
+        fn min(&self) -> Result<f64, NoError> {
+            Ok(0.0)
+        }
+
+        fn current(&self) -> Result<f64, NoError> {
+            Ok(self.0[0].get())
+        }
+
+        fn set(&self, value: f64) -> Result<(), NoError> {
+            for output in &self.0 {
+                output.set(value);
+            }
+            Ok(())
+        }
+
+        fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), NoError> {
+            for output in &self.0 {
+                output.set(next(output.get()).max(0.0).min(100.0));
+            }
+            Ok(())
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn curves_keep_the_ends_fixed() {
+        let curve = Curve::default();
+        assert_eq!(curve.to_perceived(0.0), 0.0);
+        assert_eq!(curve.to_perceived(1.0), 1.0);
+        assert_eq!(curve.to_linear(0.0), 0.0);
+        assert_eq!(curve.to_linear(1.0), 1.0);
+    }
+
+    #[test]
+    fn curves_round_trip() {
+        let curve = Curve::new(2.2);
+        for i in 0..11 {
+            let linear = i as f64 / 10.0;
+            assert!(close(curve.to_linear(curve.to_perceived(linear)), linear));
+        }
+    }
+
+    #[test]
+    fn perceived_brightness_rises_faster_at_the_bottom() {
+        let curve = Curve::default();
+        assert!(curve.to_perceived(0.1) > 0.1);
+        assert!(curve.to_linear(0.5) < 0.5);
+    }
+
+    #[test]
+    fn linear_curves_change_nothing() {
+        let curve = Curve::linear();
+        assert_eq!(curve.to_perceived(0.3), 0.3);
+        assert_eq!(curve.to_linear(0.3), 0.3);
+    }
+
+    #[test]
+    fn curves_clamp_out_of_range_values() {
+        let curve = Curve::default();
+        assert_eq!(curve.to_perceived(-0.5), 0.0);
+        assert_eq!(curve.to_linear(1.5), 1.0);
+    }
+
+    #[test]
+    fn steps_each_output_from_its_own_perceived_level() {
+        let curve = Curve::default();
+        let outputs = TwoOutputs([Cell::new(100.0 * curve.to_linear(0.2)),
+                                  Cell::new(100.0 * curve.to_linear(0.6))]);
+        Perceptual::new(&outputs, curve).change_n_clip(10.0).unwrap();
+
+        assert!(close(curve.to_perceived(outputs.0[0].get() / 100.0), 0.3));
+        assert!(close(curve.to_perceived(outputs.0[1].get() / 100.0), 0.7));
+    }
+
+    #[test]
+    fn parses_positive_exponents_only() {
+        assert!(close("2.2".parse::<Curve>().unwrap().to_linear(0.5), 0.5f64.powf(2.2)));
+        assert!("0".parse::<Curve>().is_err());
+        assert!("-1".parse::<Curve>().is_err());
+        assert!("inf".parse::<Curve>().is_err());
+        assert!("NaN".parse::<Curve>().is_err());
+        assert!("bright".parse::<Curve>().is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::error::Error as ErrorTrait;
+use std::fmt::{
+    Display,
+    Formatter,
+};
+use std::fmt::Error as FmtError;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::Brightness;
+use super::xcb::XcbBrightness;
+use super::xcb::Error as XcbError;
+use super::fs::FsBrightness;
+use super::fs::Error as FsError;
+use super::dbus::{
+    DbusBrightness,
+    DbusOptions,
+};
+use super::dbus::DbusError;
+use super::gamma::GammaBrightness;
+
+pub const BACKEND_ENV: &'static str = "SYSHOOKS_BRIGHTNESS_BACKEND";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Xcb,
+    Fs,
+    Logind,
+    Dbus,
+    Gamma,
+}
+
+/// The order backends are tried in when none is forced. `Fs` already
+/// writes through logind when sysfs isn't writable, so `Logind` is only
+/// used when asked for. `Gamma` dims in software and comes last, for
+/// screens with no backlight at all.
+pub const PROBE_ORDER: [Backend; 4] = [Backend::Xcb, Backend::Fs, Backend::Dbus, Backend::Gamma];
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Backend::Xcb => "xcb",
+            Backend::Fs => "fs",
+            Backend::Logind => "logind",
+            Backend::Dbus => "dbus",
+            Backend::Gamma => "gamma",
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "xcb" => Ok(Backend::Xcb),
+            "fs" => Ok(Backend::Fs),
+            "logind" => Ok(Backend::Logind),
+            "dbus" => Ok(Backend::Dbus),
+            "gamma" => Ok(Backend::Gamma),
+            _ => Err(Error::UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+pub enum AnyBrightness {
+    Xcb(XcbBrightness),
+    Fs(FsBrightness),
+    Dbus(DbusBrightness),
+    Gamma(GammaBrightness),
+}
+
+impl AnyBrightness {
+    /// Opens the given backend and checks that it can read the brightness.
+    /// `device` names the RandR output or sysfs backlight to use with the
+    /// xcb, gamma and fs backends, `sysfs` is where the fs backend looks and
+    /// `dbus` where the dbus backend and logind are found.
+    pub fn open(backend: Backend, device: Option<&str>, sysfs: &Path, dbus: &DbusOptions)
+        -> Result<Self, Error>
+    {
+        let control = match backend {
+            Backend::Xcb => {
+                let mut xcb = try!(XcbBrightness::connect());
+                try!(xcb.select(device));
+                AnyBrightness::Xcb(xcb)
+            },
+            Backend::Fs => {
+                let fs = try!(FsBrightness::find(sysfs, device));
+                if fs.writable() {
+                    AnyBrightness::Fs(fs)
+                } else {
+                    AnyBrightness::Fs(try!(fs.through_logind(dbus)))
+                }
+            },
+            Backend::Logind => {
+                let fs = try!(FsBrightness::find(sysfs, device));
+                AnyBrightness::Fs(try!(fs.through_logind(dbus)))
+            },
+            Backend::Dbus => AnyBrightness::Dbus(try!(DbusBrightness::open(dbus.clone()))),
+            Backend::Gamma => AnyBrightness::Gamma(try!(GammaBrightness::connect(device))),
+        };
+        try!(control.current());
+        Ok(control)
+    }
+
+    /// Opens `forced` if given, otherwise the first backend in
+    /// `PROBE_ORDER` that works. D-Bus daemons can't be pointed at a
+    /// device, so they are skipped when one is named and the output is left
+    /// to gamma.
+    pub fn probe(forced: Option<Backend>, device: Option<&str>, sysfs: &Path, dbus: &DbusOptions)
+        -> Result<Self, Error>
+    {
+        if let Some(backend) = forced {
+            return AnyBrightness::open(backend, device, sysfs, dbus);
+        }
+
+        let mut errors = Vec::new();
+        for backend in PROBE_ORDER.iter() {
+            if *backend == Backend::Dbus && device.is_some() {
+                continue;
+            }
+            match AnyBrightness::open(*backend, device, sysfs, dbus) {
+                Ok(control) => return Ok(control),
+                Err(e) => errors.push(e),
+            }
+        }
+        Err(Error::NoBackend(errors))
+    }
+
+    /// The backend in use. An fs backend writing through logind is still `Fs`.
+    pub fn backend(&self) -> Backend {
+        match *self {
+            AnyBrightness::Xcb(_) => Backend::Xcb,
+            AnyBrightness::Fs(_) => Backend::Fs,
+            AnyBrightness::Dbus(_) => Backend::Dbus,
+            AnyBrightness::Gamma(_) => Backend::Gamma,
+        }
+    }
+
+    /// The name of the single device being controlled, if there is one.
+    /// For D-Bus this is the API of the daemon doing the controlling.
+    pub fn device(&self) -> Option<&str> {
+        match *self {
+            AnyBrightness::Xcb(ref b) if b.selected().is_some() => b.selected(),
+            AnyBrightness::Xcb(ref b) if b.displays().len() == 1 && b.unlit().is_none() => {
+                Some(b.displays()[0].name())
+            },
+            AnyBrightness::Xcb(_) => None,
+            AnyBrightness::Fs(ref b) => b.name(),
+            AnyBrightness::Dbus(ref b) => Some(b.api().name()),
+            AnyBrightness::Gamma(ref b) => b.output(),
+        }
+    }
+}
+
+impl Brightness for AnyBrightness {
+    type E = Error;
+
+    fn max(&self) -> Result<f64, Error> {
+        match *self {
+            AnyBrightness::Xcb(ref b) => b.max().map_err(Error::from),
+            AnyBrightness::Fs(ref b) => b.max().map_err(Error::from),
+            AnyBrightness::Dbus(ref b) => b.max().map_err(Error::from),
+            AnyBrightness::Gamma(ref b) => b.max().map_err(Error::from),
+        }
+    }
+
+    fn min(&self) -> Result<f64, Error> {
+        match *self {
+            AnyBrightness::Xcb(ref b) => b.min().map_err(Error::from),
+            AnyBrightness::Fs(ref b) => b.min().map_err(Error::from),
+            AnyBrightness::Dbus(ref b) => b.min().map_err(Error::from),
+            AnyBrightness::Gamma(ref b) => b.min().map_err(Error::from),
+        }
+    }
+
+    fn current(&self) -> Result<f64, Error> {
+        match *self {
+            AnyBrightness::Xcb(ref b) => b.current().map_err(Error::from),
+            AnyBrightness::Fs(ref b) => b.current().map_err(Error::from),
+            AnyBrightness::Dbus(ref b) => b.current().map_err(Error::from),
+            AnyBrightness::Gamma(ref b) => b.current().map_err(Error::from),
+        }
+    }
+
+    fn set(&self, value: f64) -> Result<(), Error> {
+        match *self {
+            AnyBrightness::Xcb(ref b) => b.set(value).map_err(Error::from),
+            AnyBrightness::Fs(ref b) => b.set(value).map_err(Error::from),
+            AnyBrightness::Dbus(ref b) => b.set(value).map_err(Error::from),
+            AnyBrightness::Gamma(ref b) => b.set(value).map_err(Error::from),
+        }
+    }
+
+    fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), Error> {
+        match *self {
+            AnyBrightness::Xcb(ref b) => b.change_with(next).map_err(Error::from),
+            AnyBrightness::Fs(ref b) => b.change_with(next).map_err(Error::from),
+            AnyBrightness::Dbus(ref b) => b.change_with(next).map_err(Error::from),
+            AnyBrightness::Gamma(ref b) => b.change_with(next).map_err(Error::from),
+        }
+    }
+}
+
+impl From<XcbError> for Error {
+    #[inline]
+    fn from(err: XcbError) -> Self {
+        Error::Xcb(err)
+    }
+}
+
+impl From<FsError> for Error {
+    #[inline]
+    fn from(err: FsError) -> Self {
+        Error::Fs(err)
+    }
+}
+
+impl From<DbusError> for Error {
+    #[inline]
+    fn from(err: DbusError) -> Self {
+        Error::Dbus(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Xcb(XcbError),
+    Fs(FsError),
+    Dbus(DbusError),
+    UnknownBackend(String),
+    NoBackend(Vec<Error>),
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Xcb(ref e) => e.description(),
+            Error::Fs(ref e) => e.description(),
+            Error::Dbus(ref e) => e.description(),
+            Error::UnknownBackend(_) => "Unknown brightness backend",
+            Error::NoBackend(_) => "No brightness backend is available",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Xcb(ref e) => Some(e),
+            Error::Fs(ref e) => Some(e),
+            Error::Dbus(ref e) => Some(e),
+            Error::UnknownBackend(_) => None,
+            Error::NoBackend(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FmtError> {
+        match *self {
+            Error::Xcb(ref e) => write!(fmt, "xcb: {}", e),
+            Error::Fs(ref e) => write!(fmt, "fs: {}", e),
+            Error::Dbus(ref e) => write!(fmt, "dbus: {}", e),
+            Error::UnknownBackend(ref name) => {
+                write!(fmt, "Unknown brightness backend '{}' (expected xcb, fs, logind, dbus or gamma)", name)
+            },
+            Error::NoBackend(ref errors) => {
+                try!(fmt.write_str(self.description()));
+                for e in errors {
+                    try!(write!(fmt, "\n    {}", e));
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate dbus;
+
+    use self::dbus::Message;
+
+    use super::*;
+    use super::super::dbus::Bus;
+    use super::super::fs::BACKLIGHT_DIR;
+    use testing::{
+        scratch_dir,
+        write,
+        DbusDaemon,
+    };
+
+    /// A bus with a brightness daemon at 30% under `org.example.Power`,
+    /// and options pointing the dbus backend at it.
+    fn brightness_daemon() -> Option<(DbusDaemon, DbusOptions)> {
+        let daemon = match DbusDaemon::start() {
+            Some(d) => d,
+            None => return None,
+        };
+        daemon.serve("org.example.Power", &["/org/example/Power"], |call| {
+            match call.member().map(|m| m.to_string()) {
+                Some(ref m) if m == "GetPercentage" => call.method_return().append1(30u32),
+                _ => Message::new_error(call, "org.freedesktop.DBus.Error.UnknownMethod", "")
+                    .unwrap(),
+            }
+        });
+        let options = DbusOptions::default()
+            .bus(Bus::Address(daemon.address().to_string()))
+            .destination(Some("org.example.Power".to_string()))
+            .path(Some("/org/example/Power".to_string()));
+        Some((daemon, options))
+    }
+
+    #[test]
+    fn opens_the_fs_backend_under_the_given_root() {
+        let root = scratch_dir("probe-fs");
+        let dir = root.join(BACKLIGHT_DIR).join("acpi_video0");
+        write(&dir.join("max_brightness"), "15\n");
+        write(&dir.join("brightness"), "3\n");
+
+        let control = AnyBrightness::open(Backend::Fs, None, &root, &DbusOptions::default()).unwrap();
+        assert_eq!(control.backend(), Backend::Fs);
+        assert_eq!(control.device(), Some("acpi_video0"));
+        assert_eq!(control.current().unwrap(), 3.0);
+        assert_eq!(control.max().unwrap(), 15.0);
+
+        let empty = scratch_dir("probe-empty");
+        match AnyBrightness::open(Backend::Fs, None, &empty, &DbusOptions::default()) {
+            Err(Error::Fs(_)) => {},
+            _ => panic!("found a backlight in an empty tree"),
+        }
+    }
+
+    #[test]
+    fn falls_through_to_the_next_backend() {
+        let (_daemon, options) = match brightness_daemon() {
+            Some(d) => d,
+            None => return,
+        };
+        let empty = scratch_dir("probe-none");
+        let control = AnyBrightness::probe(None, None, &empty, &options).unwrap();
+        assert_eq!(control.backend(), Backend::Dbus);
+        assert_eq!(control.current().unwrap(), 30.0);
+
+        let root = scratch_dir("probe-fs-first");
+        let dir = root.join(BACKLIGHT_DIR).join("acpi_video0");
+        write(&dir.join("max_brightness"), "15\n");
+        write(&dir.join("brightness"), "3\n");
+        let control = AnyBrightness::probe(None, None, &root, &options).unwrap();
+        assert_eq!(control.backend(), Backend::Fs);
+    }
+
+    #[test]
+    fn skips_dbus_when_a_device_is_named() {
+        let (_daemon, options) = match brightness_daemon() {
+            Some(d) => d,
+            None => return,
+        };
+        let root = scratch_dir("probe-device");
+        match AnyBrightness::probe(None, Some("eDP-1"), &root, &options) {
+            Err(Error::NoBackend(ref errors)) => {
+                assert_eq!(errors.len(), PROBE_ORDER.len() - 1);
+                assert!(!errors.iter().any(|e| match *e {
+                    Error::Dbus(_) => true,
+                    _ => false,
+                }));
+            },
+            Err(e) => panic!("expected every backend to fail, got {}", e),
+            Ok(control) => panic!("probed {} for a missing device", control.backend().name()),
+        }
+    }
+
+    #[test]
+    fn parses_backend_names() {
+        assert_eq!("logind".parse::<Backend>().unwrap(), Backend::Logind);
+        for backend in PROBE_ORDER.iter() {
+            assert_eq!(backend.name().parse::<Backend>().unwrap(), *backend);
+        }
+        assert!("sysfs".parse::<Backend>().is_err());
+    }
+}
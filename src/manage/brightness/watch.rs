@@ -0,0 +1,148 @@
+extern crate libc;
+
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+use super::fs::Error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Change {
+    pub old: f64,
+    pub new: f64,
+}
+
+/// Reports changes to sysfs brightness files, whoever makes them.
+///
+/// The backlight core calls `sysfs_notify` on `actual_brightness` when
+/// firmware changes the level, which wakes `poll` with `POLLPRI`. Drivers
+/// that don't are caught by re-reading the values every `interval`.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    last: Vec<Option<f64>>,
+}
+
+impl Watcher {
+    /// Watches every file in `paths`. A change to any of them is reported
+    /// once, with the values of the first one that changed.
+    pub fn new(paths: Vec<PathBuf>, interval: Duration) -> Self {
+        Watcher {
+            last: vec![None; paths.len()],
+            paths: paths,
+            interval: interval,
+        }
+    }
+
+    fn read(path: &PathBuf) -> Result<(f64, File), Error> {
+        let mut buffer = String::new();
+        let mut file = try!(File::open(path));
+        try!(file.read_to_string(&mut buffer));
+        Ok((try!(buffer.trim().parse()), file))
+    }
+
+    /// Sleeps until one of `files` is notified or `interval` has passed.
+    fn wait(&self, files: &[File]) {
+        let ms = self.interval.as_secs() * 1000 + self.interval.subsec_nanos() as u64 / 1_000_000;
+        let mut fds: Vec<libc::pollfd> = files.iter().map(|file| libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLPRI | libc::POLLERR,
+            revents: 0,
+        }).collect();
+        let woken = unsafe {
+            libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms as libc::c_int)
+        };
+        if woken < 0 {
+            sleep(self.interval);
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<Change, Error>;
+
+    /// Blocks until a value differs from the last one seen. Never ends.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut files = Vec::with_capacity(self.paths.len());
+            let mut change = None;
+
+            for (path, last) in self.paths.iter().zip(self.last.iter_mut()) {
+                let (value, file) = match Watcher::read(path) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        sleep(self.interval);
+                        return Some(Err(e));
+                    },
+                };
+                files.push(file);
+
+                match *last {
+                    Some(old) if old != value && change.is_none() => {
+                        change = Some(Change {
+                            old: old,
+                            new: value,
+                        });
+                    },
+                    _ => (),
+                }
+                *last = Some(value);
+            }
+
+            if let Some(change) = change {
+                return Some(Ok(change));
+            }
+            self.wait(&files);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{
+        scratch_dir,
+        write,
+        ScratchDir,
+    };
+
+    /// A watcher on `actual_brightness` and `brightness`, both at 10 and
+    /// already seen, in a directory that lasts as long as the guard.
+    fn watch_both(name: &str) -> (ScratchDir, Watcher, PathBuf, PathBuf) {
+        let dir = scratch_dir(name);
+        let (actual, brightness) = (dir.join("actual_brightness"), dir.join("brightness"));
+        write(&actual, "10\n");
+        write(&brightness, "10\n");
+
+        let mut watcher = Watcher::new(vec![actual.clone(), brightness.clone()],
+                                       Duration::from_millis(10));
+        watcher.last = vec![Some(10.0), Some(10.0)];
+        (dir, watcher, actual, brightness)
+    }
+
+    #[test]
+    fn reports_changes_to_any_file() {
+        let (_dir, mut watcher, _, brightness) = watch_both("watch-any");
+        write(&brightness, "20\n");
+
+        let change = watcher.next().unwrap().unwrap();
+        assert_eq!((change.old, change.new), (10.0, 20.0));
+    }
+
+    #[test]
+    fn reports_a_change_to_both_files_once() {
+        let (_dir, mut watcher, actual, brightness) = watch_both("watch-both");
+        write(&actual, "30\n");
+        write(&brightness, "30\n");
+
+        let first = watcher.next().unwrap().unwrap();
+        assert_eq!((first.old, first.new), (10.0, 30.0));
+
+        write(&actual, "40\n");
+        let second = watcher.next().unwrap().unwrap();
+        assert_eq!((second.old, second.new), (30.0, 40.0));
+    }
+}
@@ -3,35 +3,237 @@ extern crate xcb as xcb_ffi;
 use std::io::{
     Write,
     stderr,
-    Error,
-    ErrorKind,
 };
-
-use self::xcb_ffi::base::Connection;
+use std::cell::Cell;
+use std::error::Error as ErrorTrait;
+use std::fmt;
+
+use self::xcb_ffi::base::{
+    Connection,
+    ConnError,
+    GenericError,
+    GenericEvent,
+};
 use self::xcb_ffi::xproto;
-use self::xcb_ffi::xproto::Atom;
+use self::xcb_ffi::xproto::{
+    Atom,
+    Window,
+};
 use self::xcb_ffi::ffi::xproto as xproto_ffi;
 use self::xcb_ffi::randr;
 use self::xcb_ffi::randr::Output;
 use self::xcb_ffi::ffi::randr as randr_ffi;
 
+use super::gamma::{
+    Gamma,
+    level_for_light,
+    light_for_level,
+};
+
 #[derive(Debug)]
 pub struct Display {
+    name: String,
     output: Output,
     min: i32,
     max: i32,
-    current: i32,
+    current: Cell<i32>,
+}
+
+impl Display {
+    /// The RandR name of the output, e.g. `eDP-1`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The last brightness seen or set, without asking the server.
+    fn cached(&self) -> f64 {
+        let min = self.min as f64;
+        let max = self.max as f64;
+        100f64 * (self.current.get() as f64 - min) / (max - min)
+    }
+}
+
+/// Something that changed on the X server since the last look.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// An output's backlight changed and is now at this percentage.
+    Changed(String, f64),
+    /// Outputs were plugged, unplugged or reconfigured and the display list
+    /// has been rebuilt.
+    OutputsChanged,
 }
 
 pub struct XcbBrightness {
     connection: Connection,
     atom: Atom,
+    roots: Vec<Window>,
+    first_event: u8,
     displays: Vec<Display>,
+    selected: Option<String>,
 }
 
 impl XcbBrightness {
-    pub fn connect() -> Self {
-        create_session()
+    pub fn connect() -> Result<Self, Error> {
+        XcbBrightness::connect_to(None)
+    }
+
+    /// Connects to the X server called `display`, e.g. `:1`, or to
+    /// `$DISPLAY` if no name is given.
+    pub fn connect_to(display: Option<&str>) -> Result<Self, Error> {
+        create_session(display)
+    }
+
+    /// Restricts control to the output called `name`, or to every active
+    /// output if no name is given. Outputs without a backlight are dimmed
+    /// through their gamma ramps.
+    pub fn select(&mut self, name: Option<&str>) -> Result<(), Error> {
+        if let Some(n) = name {
+            if !self.displays.iter().any(|d| d.name == n) {
+                try!(Gamma::new(&self.connection, &self.roots, Some(n)));
+            }
+        }
+        self.selected = name.map(|n| n.to_string());
+        Ok(())
+    }
+
+    /// The output chosen with `select`, if any.
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_ref().map(|s| s as &str)
+    }
+
+    /// The outputs with a backlight currently being controlled. Empty if
+    /// the selected output has been unplugged or has no backlight.
+    pub fn displays(&self) -> &[Display] {
+        match self.selected {
+            Some(ref name) => match self.displays.iter().position(|d| &d.name == name) {
+                Some(i) => &self.displays[i..i + 1],
+                None => &[],
+            },
+            None => &self.displays,
+        }
+    }
+
+    pub fn current_of(&self, display: &Display) -> Result<f64, Error> {
+        match backlight_get(&self.connection, display.output, self.atom) {
+            Ok(Some(current)) => {
+                display.current.set(current);
+                Ok(display.cached())
+            },
+            Ok(None) => Err(Error::PropertyQuery(display.name.clone())),
+            Err(_) => Err(Error::OutputVanished(display.name.clone())),
+        }
+    }
+
+    pub fn set_of(&self, display: &Display, value: f64) -> Result<(), Error> {
+        let min = display.min as f64;
+        let max = display.max as f64;
+        let new = (value * (max - min) / 100.0 + min).round();
+        try!(backlight_set(&self.connection, display.output, self.atom, new as u32)
+            .map_err(|_| Error::OutputVanished(display.name.clone())));
+        display.current.set(new as i32);
+        Ok(())
+    }
+
+    /// Asks the server for the RandR notifications `poll_events` and
+    /// `wait_events` report.
+    pub fn subscribe(&self) -> Result<(), Error> {
+        let mask = randr::NOTIFY_MASK_SCREEN_CHANGE
+            | randr::NOTIFY_MASK_OUTPUT_CHANGE
+            | randr::NOTIFY_MASK_OUTPUT_PROPERTY;
+        for root in &self.roots {
+            try!(randr::select_input_checked(&self.connection, *root, mask as u16)
+                .request_check()
+                .map_err(|_| Error::NoRandr));
+        }
+        Ok(())
+    }
+
+    /// Handles every event already received, without blocking.
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(event) = self.connection.poll_for_event() {
+            if let Some(e) = self.handle(event) {
+                events.push(e);
+            }
+        }
+        events
+    }
+
+    /// Blocks until at least one event arrives, then handles everything
+    /// that is queued.
+    pub fn wait_events(&mut self) -> Result<Vec<Event>, Error> {
+        loop {
+            let event = match self.connection.wait_for_event() {
+                Some(e) => e,
+                None => return Err(Error::ConnectionClosed),
+            };
+            let mut events = Vec::new();
+            if let Some(e) = self.handle(event) {
+                events.push(e);
+            }
+            events.extend(self.poll_events());
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+
+    fn handle(&mut self, event: GenericEvent) -> Option<Event> {
+        let kind = event.response_type() & !0x80;
+
+        if kind == self.first_event + randr::SCREEN_CHANGE_NOTIFY {
+            return Some(self.rescan());
+        }
+        if kind != self.first_event + randr::NOTIFY {
+            return None;
+        }
+
+        let notify: &randr::NotifyEvent = xcb_ffi::cast_event(&event);
+        match notify.subCode() as randr::Notify {
+            randr::NOTIFY_OUTPUT_CHANGE => Some(self.rescan()),
+            randr::NOTIFY_OUTPUT_PROPERTY => {
+                let property = unsafe {
+                    &*(notify.u() as *const randr::NotifyData
+                        as *const randr_ffi::xcb_randr_output_property_t)
+                };
+                if property.atom != self.atom {
+                    return None;
+                }
+                // Only the outputs picked with `select` are reported.
+                let display = match self.displays().iter().find(|d| d.output == property.output) {
+                    Some(d) => d,
+                    None => return None,
+                };
+                self.current_of(display).ok()
+                    .map(|percent| Event::Changed(display.name.clone(), percent))
+            },
+            _ => None,
+        }
+    }
+
+    /// Gamma ramps of the selected output's CRTC, or of every active CRTC,
+    /// over this connection.
+    pub fn gamma<'a>(&'a self) -> Result<Gamma<'a>, Error> {
+        Gamma::new(&self.connection, &self.roots, self.selected())
+    }
+
+    /// Gamma ramps of the controlled outputs without a backlight, which
+    /// are dimmed in software alongside the backlights. `None` if every
+    /// controlled output has a backlight.
+    pub fn unlit<'a>(&'a self) -> Option<Gamma<'a>> {
+        match self.selected {
+            Some(ref name) if self.displays.iter().any(|d| &d.name == name) => None,
+            Some(ref name) => Gamma::new(&self.connection, &self.roots, Some(name)).ok(),
+            None => {
+                let lit: Vec<&str> = self.displays.iter().map(|d| d.name()).collect();
+                Gamma::without(&self.connection, &self.roots, &lit).ok()
+            },
+        }
+    }
+
+    fn rescan(&mut self) -> Event {
+        self.displays = find_displays(&self.connection, self.atom, &self.roots);
+        Event::OutputsChanged
     }
 }
 
@@ -46,59 +248,91 @@ impl super::Brightness for XcbBrightness {
         Ok(0f64)
     }
 
+    /// Reports the first controlled output, preferring backlights. Outputs
+    /// without one report the light their gamma ramp lets through.
     fn current(&self) -> Result<f64, Error> {
-        if let Some(display) = self.displays.first() {
-            match backlight_get(&self.connection, display.output, self.atom) {
-                Some(current) => {
-                    let min = display.min as f64;
-                    let max = display.max as f64;
-                    Ok(100f64 * (current as f64 - min) / (max - min))
-                },
-                None => Err(sentinel_e()),
-            }
-        } else {
-            Err(sentinel_e())
+        if let Some(display) = self.displays().first() {
+            return self.current_of(display);
+        }
+        match self.unlit() {
+            Some(gamma) => {
+                let level = try!(gamma.brightness_of(&gamma.crtcs()[0]));
+                Ok(100f64 * light_for_level(level))
+            },
+            None => Err(Error::NoBacklightProperty),
         }
     }
 
+    /// Sets every controlled output to `value`.
     fn set(&self, value: f64) -> Result<(), Error> {
-        if let Some(display) = self.displays.first() {
-            let min = display.min as f64;
-            let max = display.max as f64;
-            let new = value * (max - min) / 100.0 + min;
-            backlight_set(&self.connection, display.output, self.atom, new as u32);
-            Ok(())
-        } else {
-            Err(sentinel_e())
+        for display in self.displays() {
+            try!(self.set_of(display, value));
+        }
+        if let Some(gamma) = self.unlit() {
+            try!(gamma.set_brightness(level_for_light(value / 100f64)));
+        }
+        Ok(())
+    }
+
+    /// Steps every controlled output from its own current value.
+    fn change_with<F: Fn(f64) -> f64>(&self, next: F) -> Result<(), Error> {
+        for display in self.displays() {
+            let current = try!(self.current_of(display));
+            let next = next(current).max(0.0).min(100.0);
+            try!(self.set_of(display, next));
         }
+        if let Some(gamma) = self.unlit() {
+            for crtc in gamma.crtcs() {
+                let current = 100f64 * light_for_level(try!(gamma.brightness_of(crtc)));
+                let next = next(current).max(0.0).min(100.0);
+                try!(gamma.set_brightness_of(crtc, level_for_light(next / 100f64)));
+            }
+        }
+        Ok(())
     }
 }
 
-fn create_session() -> XcbBrightness {
-    let mut displays = Vec::new();
-    let (connection, _) = Connection::connect(None)
-        .expect("Could not connect to the x server!");
+/// Connects to the X server called `display`, or to `$DISPLAY`, and checks
+/// for RandR 1.2. Returns the connection and the root window of every
+/// screen.
+pub fn open_randr(display: Option<&str>) -> Result<(Connection, Vec<Window>), Error> {
+    let (connection, _) = try!(Connection::connect(display).map_err(Error::NoDisplay));
 
-    let reply = randr::query_version(&connection, 1, 2).get_reply()
-        .expect("RANDR query version returned an error");
+    let reply = try!(randr::query_version(&connection, 1, 2).get_reply()
+        .map_err(|_| Error::NoRandr));
 
     if reply.major_version() != 1 || reply.minor_version() < 2 {
-        panic!("RandR version {}.{} is too old!",
-               reply.major_version(), reply.minor_version());
+        return Err(Error::RandrTooOld(reply.major_version(), reply.minor_version()));
     }
 
-    let backlight_new = xproto::intern_atom(&connection, true, "Backlight")
-        .get_reply()
-        .expect("intern backlight atom returned an error");
+    let mut roots = Vec::new();
+    let mut iter = unsafe {
+        xproto_ffi::xcb_setup_roots_iterator(connection.get_setup().ptr)
+    };
+    while iter.rem > 0 {
+        unsafe {
+            roots.push((*iter.data).root);
+            xproto_ffi::xcb_screen_next(&mut iter as *mut xproto_ffi::xcb_screen_iterator_t);
+        }
+    }
+
+    Ok((connection, roots))
+}
+
+fn create_session(display: Option<&str>) -> Result<XcbBrightness, Error> {
+    let (connection, roots) = try!(open_randr(display));
+
+    let backlight_new = try!(xproto::intern_atom(&connection, true, "Backlight").get_reply()
+        .map_err(|_| Error::NoBacklightProperty));
 
     let atom = if backlight_new.atom() == xproto_ffi::XCB_ATOM_NONE {
 
-        let backlight_legacy = xproto::intern_atom(&connection, true, "BACKLIGHT")
+        let backlight_legacy = try!(xproto::intern_atom(&connection, true, "BACKLIGHT")
             .get_reply()
-            .expect("intern legacy backlight atom returned an error");
+            .map_err(|_| Error::NoBacklightProperty));
 
         if backlight_legacy.atom() == xproto_ffi::XCB_ATOM_NONE {
-            panic!("No outputs have backlight property");
+            return Err(Error::NoBacklightProperty);
         }
 
         backlight_legacy.atom()
@@ -106,17 +340,33 @@ fn create_session() -> XcbBrightness {
         backlight_new.atom()
     };
 
-    let mut iter = unsafe {
-        xproto_ffi::xcb_setup_roots_iterator(connection.get_setup().ptr)
+    let first_event = match connection.get_extension_data(randr::id()) {
+        Some(data) => data.first_event(),
+        None => return Err(Error::NoRandr),
     };
 
-    while iter.rem > 0 {
+    let displays = find_displays(&connection, atom, &roots);
 
-        let root = unsafe {
-            (*iter.data).root
-        };
+    if displays.is_empty() {
+        return Err(Error::NoBacklightProperty);
+    }
 
-        let resources_cookie = randr::get_screen_resources_current(&connection, root);
+    Ok(XcbBrightness {
+        connection: connection,
+        atom: atom,
+        roots: roots,
+        first_event: first_event,
+        displays: displays,
+        selected: None,
+    })
+}
+
+/// Finds every output on the `roots` screens with a usable backlight.
+fn find_displays(connection: &Connection, atom: Atom, roots: &[Window]) -> Vec<Display> {
+    let mut displays = Vec::new();
+
+    for root in roots {
+        let resources_cookie = randr::get_screen_resources_current(connection, *root);
         let resources = match resources_cookie.get_reply() {
             Ok(reply) => reply,
             Err(e) => {
@@ -125,6 +375,7 @@ fn create_session() -> XcbBrightness {
             },
         };
 
+        let config_timestamp = resources.config_timestamp();
         let outputs = unsafe {
             randr_ffi::xcb_randr_get_screen_resources_current_outputs(resources.ptr)
         };
@@ -134,12 +385,12 @@ fn create_session() -> XcbBrightness {
                 *outputs.offset(o as isize)
             };
 
-            let current = match backlight_get(&connection, output, atom) {
-                Some(c) => c,
-                None => continue,
+            let current = match backlight_get(connection, output, atom) {
+                Ok(Some(c)) => c,
+                _ => continue,
             };
 
-            let prop = match randr::query_output_property(&connection, output, atom).get_reply() {
+            let prop = match randr::query_output_property(connection, output, atom).get_reply() {
                 Ok(reply) => reply,
                 Err(e) => {
                     writeln!(&mut stderr(), "backlight info query error: {:?}", e).ok();
@@ -159,44 +410,49 @@ fn create_session() -> XcbBrightness {
                 (*range, *range.offset(1))
             };
 
+            let name = match randr::get_output_info(connection, output, config_timestamp)
+                .get_reply() {
+                Ok(info) => String::from_utf8_lossy(info.name()).into_owned(),
+                Err(e) => {
+                    writeln!(&mut stderr(), "output info query error: {:?}", e).ok();
+                    continue
+                },
+            };
+
             displays.push(Display {
+                name: name,
                 output: output,
                 min: min,
                 max: max,
-                current: current,
+                current: Cell::new(current),
             });
         }
-
-        unsafe {
-            xproto_ffi::xcb_screen_next(&mut iter as *mut xproto_ffi::xcb_screen_iterator_t);
-        }
     }
 
-    XcbBrightness {
-        connection: connection,
-        atom: atom,
-        displays: displays,
-    }
+    displays
 }
 
-fn backlight_get(connection: &Connection, output: Output, atom: Atom) -> Option<i32> {
-    let property = match randr::get_output_property(
+/// Reads the backlight property of `output`. Fails if the request itself
+/// fails and is `None` if the property is missing or malformed.
+fn backlight_get(connection: &Connection, output: Output, atom: Atom)
+    -> Result<Option<i32>, GenericError>
+{
+    let property = try!(randr::get_output_property(
             connection, output, atom, xproto_ffi::XCB_ATOM_NONE, 0, 4, false, false
-    ).get_reply() {
-        Ok(p) => p,
-        Err(_) => return None,
-    };
+    ).get_reply());
     if property.num_items() != 1 || property.format() != 32 {
-        None
+        Ok(None)
     } else {
-        Some(unsafe {
+        Ok(Some(unsafe {
             *(randr_ffi::xcb_randr_get_output_property_data(property.ptr) as *const i32)
-        })
+        }))
     }
 }
 
-fn backlight_set(connection: &Connection, output: Output, atom: Atom, value: u32) {
-    randr::change_output_property(
+fn backlight_set(connection: &Connection, output: Output, atom: Atom, value: u32)
+    -> Result<(), GenericError>
+{
+    randr::change_output_property_checked(
         connection,
         output,
         atom,
@@ -204,12 +460,150 @@ fn backlight_set(connection: &Connection, output: Output, atom: Atom, value: u32
         32,
         xproto_ffi::XCB_PROP_MODE_REPLACE as u8,
         &[value as i32],
-    );
-    connection.flush();
+    ).request_check()
 }
 
-fn sentinel_e() -> Error {
-    Error::new(ErrorKind::Other, "XcbBrightness does not yet support good error handling.")
+#[derive(Debug)]
+pub enum Error {
+    NoDisplay(ConnError),
+    NoRandr,
+    RandrTooOld(u32, u32),
+    NoBacklightProperty,
+    PropertyQuery(String),
+    OutputVanished(String),
+    NoSuchOutput(String),
+    ConnectionClosed,
+    NoCrtc,
+    GammaQuery,
 }
 
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::NoDisplay(_) => "Could not connect to the X server",
+            Error::NoRandr => "The X server does not support RandR",
+            Error::RandrTooOld(_, _) => "The X server's RandR version is too old",
+            Error::NoBacklightProperty => "No outputs have a backlight property",
+            Error::PropertyQuery(_) => "Could not read an output's backlight property",
+            Error::OutputVanished(_) => "An output has disappeared",
+            Error::NoSuchOutput(_) => "No active output by that name",
+            Error::ConnectionClosed => "The connection to the X server was closed",
+            Error::NoCrtc => "No active CRTC drives the requested outputs",
+            Error::GammaQuery => "Could not read or write a CRTC's gamma ramp",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::NoDisplay(ref e) => write!(fmt, "{}: {:?}", self.description(), e),
+            Error::RandrTooOld(major, minor) => {
+                write!(fmt, "RandR version {}.{} is too old, need 1.2", major, minor)
+            },
+            Error::PropertyQuery(ref name) => {
+                write!(fmt, "Could not read the backlight property of {}", name)
+            },
+            Error::OutputVanished(ref name) => write!(fmt, "Output {} has disappeared", name),
+            Error::NoSuchOutput(ref name) => write!(fmt, "No active output named {}", name),
+            _ => fmt.write_str(self.description()),
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use super::*;
+    use super::super::Brightness;
+    use testing::Xvfb;
+
+    /// Gives the first output of `root` a `Backlight` property running
+    /// from `min` to `max`, as a laptop panel's driver would.
+    fn add_backlight(connection: &Connection, root: Window, min: i32, max: i32, value: i32)
+        -> Output
+    {
+        let resources = randr::get_screen_resources_current(connection, root)
+            .get_reply().unwrap();
+        let output = resources.outputs()[0];
+        let atom = xproto::intern_atom(connection, false, "Backlight").get_reply().unwrap().atom();
+        randr::configure_output_property_checked(connection, output, atom, false, true, &[min, max])
+            .request_check().unwrap();
+        set_backlight(connection, output, value);
+        output
+    }
+
+    fn set_backlight(connection: &Connection, output: Output, value: i32) {
+        let atom = xproto::intern_atom(connection, true, "Backlight").get_reply().unwrap().atom();
+        backlight_set(connection, output, atom, value as u32).unwrap();
+    }
+
+    #[test]
+    fn describes_what_went_wrong() {
+        assert_eq!(Error::RandrTooOld(1, 1).to_string(), "RandR version 1.1 is too old, need 1.2");
+        assert_eq!(Error::OutputVanished("eDP-1".to_string()).to_string(),
+                   "Output eDP-1 has disappeared");
+        assert_eq!(Error::NoCrtc.to_string(), "No active CRTC drives the requested outputs");
+        assert_eq!(Error::RandrTooOld(1, 1).description(), "The X server's RandR version is too old");
+    }
+
+    #[test]
+    #[ignore]
+    fn needs_a_backlight_property() {
+        let xvfb = Xvfb::start();
+        match XcbBrightness::connect_to(Some(xvfb.display())) {
+            Err(Error::NoBacklightProperty) => {},
+            Err(e) => panic!("expected no backlight property, got {}", e),
+            Ok(_) => panic!("found a backlight on a bare Xvfb"),
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn reads_and_sets_the_backlight_property() {
+        let xvfb = Xvfb::start();
+        let (connection, roots) = open_randr(Some(xvfb.display())).unwrap();
+        let output = add_backlight(&connection, roots[0], 0, 10, 4);
+
+        let mut xcb = XcbBrightness::connect_to(Some(xvfb.display())).unwrap();
+        assert_eq!(xcb.displays().len(), 1);
+        assert_eq!(xcb.current().unwrap(), 40.0);
+
+        xcb.set(70.0).unwrap();
+        assert_eq!(backlight_get(&connection, output, xcb.atom).unwrap(), Some(7));
+        xcb.set(66.6).unwrap();
+        assert_eq!(backlight_get(&connection, output, xcb.atom).unwrap(), Some(7));
+
+        let name = xcb.displays()[0].name().to_string();
+        xcb.select(Some(&name)).unwrap();
+        match xcb.select(Some("nowhere")) {
+            Err(Error::NoSuchOutput(ref n)) if n == "nowhere" => {},
+            other => panic!("expected no such output, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn reports_backlight_changes() {
+        let xvfb = Xvfb::start();
+        let (connection, roots) = open_randr(Some(xvfb.display())).unwrap();
+        let output = add_backlight(&connection, roots[0], 0, 100, 20);
+
+        let mut xcb = XcbBrightness::connect_to(Some(xvfb.display())).unwrap();
+        xcb.subscribe().unwrap();
+        set_backlight(&connection, output, 60);
+
+        for _ in 0..100 {
+            for event in xcb.poll_events() {
+                if let Event::Changed(_, percent) = event {
+                    assert_eq!(percent, 60.0);
+                    return;
+                }
+            }
+            sleep(Duration::from_millis(20));
+        }
+        panic!("no backlight change reported");
+    }
+}
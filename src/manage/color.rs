@@ -0,0 +1,70 @@
+/// Daylight white, the temperature that leaves the screen untouched.
+pub const NEUTRAL: f64 = 6500.0;
+pub const MIN_TEMPERATURE: f64 = 1000.0;
+pub const MAX_TEMPERATURE: f64 = 25000.0;
+
+/// Red, green and blue levels from 0 to 1 for a blackbody at `kelvin`,
+/// scaled so `NEUTRAL` is pure white and the brightest channel is 1.
+pub fn temperature_levels(kelvin: f64) -> [f64; 3] {
+    let raw = blackbody(kelvin);
+    let white = blackbody(NEUTRAL);
+    let levels = [raw[0] / white[0], raw[1] / white[1], raw[2] / white[2]];
+    let top = levels[0].max(levels[1]).max(levels[2]);
+    [levels[0] / top, levels[1] / top, levels[2] / top]
+}
+
+/// Tanner Helland's fit of the blackbody colour curve, in `[0, 1]`.
+fn blackbody(kelvin: f64) -> [f64; 3] {
+    let t = kelvin.max(MIN_TEMPERATURE).min(MAX_TEMPERATURE) / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let green = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+
+    let clamp = |c: f64| c.max(0.0).min(255.0) / 255.0;
+    [clamp(red), clamp(green), clamp(blue)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neutral_is_white() {
+        assert_eq!(temperature_levels(NEUTRAL), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn warm_drops_blue_and_cool_drops_red() {
+        let warm = temperature_levels(3000.0);
+        assert_eq!(warm[0], 1.0);
+        assert!(warm[1] < 1.0 && warm[2] < warm[1]);
+
+        let cool = temperature_levels(10000.0);
+        assert_eq!(cool[2], 1.0);
+        assert!(cool[0] < cool[1]);
+    }
+
+    #[test]
+    fn clamps_to_the_supported_range() {
+        assert_eq!(temperature_levels(100.0), temperature_levels(MIN_TEMPERATURE));
+        assert_eq!(temperature_levels(1e6), temperature_levels(MAX_TEMPERATURE));
+        for level in temperature_levels(MIN_TEMPERATURE).iter() {
+            assert!(*level >= 0.0 && *level <= 1.0);
+        }
+    }
+}
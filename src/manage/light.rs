@@ -0,0 +1,227 @@
+use std::fs::{
+    self,
+    File,
+};
+use std::path::{
+    PathBuf,
+    Path,
+};
+use std::io::Read;
+use std::io::Error as IoError;
+use std::num::ParseFloatError;
+use std::error::Error as ErrorTrait;
+use std::fmt::{
+    Display,
+    Formatter,
+};
+use std::fmt::Error as FmtError;
+
+/// Relative to the sysfs root.
+pub const IIO_DIR: &'static str = "bus/iio/devices";
+
+/// An IIO ambient light sensor.
+pub struct LightSensor {
+    name: String,
+    input_path: PathBuf,
+    scale: f64,
+    offset: f64,
+}
+
+impl LightSensor {
+    /// Opens the sensor in `device_dir`, which must have an
+    /// `in_illuminance_input` or `in_illuminance_raw` channel.
+    pub fn at(device_dir: &Path) -> Result<Self, Error> {
+        let name = device_dir.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("")
+            .to_string();
+
+        // `input` is already in lux, `raw` needs scale and offset.
+        let input = device_dir.join("in_illuminance_input");
+        if input.exists() {
+            return Ok(LightSensor {
+                name: name,
+                input_path: input,
+                scale: 1.0,
+                offset: 0.0,
+            });
+        }
+
+        let raw = device_dir.join("in_illuminance_raw");
+        if !raw.exists() {
+            return Err(Error::NoSensor);
+        }
+        let scale = read_optional(&device_dir.join("in_illuminance_scale"), 1.0);
+        let offset = read_optional(&device_dir.join("in_illuminance_offset"), 0.0);
+
+        Ok(LightSensor {
+            name: name,
+            input_path: raw,
+            scale: try!(scale),
+            offset: try!(offset),
+        })
+    }
+
+    /// Uses the device called `name` under `iio_dir`, or the first one
+    /// that measures illuminance.
+    pub fn find(iio_dir: &Path, name: Option<&str>) -> Result<Self, Error> {
+        let mut dirs = Vec::new();
+        for entry in try!(fs::read_dir(iio_dir)) {
+            dirs.push(try!(entry).path());
+        }
+        dirs.sort();
+
+        for dir in dirs {
+            if let Some(n) = name {
+                if dir.file_name().and_then(|f| f.to_str()) != Some(n) {
+                    continue;
+                }
+            }
+            if let Ok(sensor) = LightSensor::at(&dir) {
+                return Ok(sensor);
+            }
+        }
+        Err(Error::NoSensor)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The current illuminance in lux.
+    pub fn lux(&self) -> Result<f64, Error> {
+        let raw = try!(read_value(&self.input_path));
+        Ok((raw + self.offset) * self.scale)
+    }
+}
+
+fn read_value(path: &Path) -> Result<f64, Error> {
+    let mut buffer = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut buffer));
+    Ok(try!(buffer.trim().parse()))
+}
+
+fn read_optional(path: &Path, default: f64) -> Result<f64, Error> {
+    if path.exists() {
+        read_value(path)
+    } else {
+        Ok(default)
+    }
+}
+
+impl From<IoError> for Error {
+    #[inline]
+    fn from(err: IoError) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseFloatError> for Error {
+    #[inline]
+    fn from(err: ParseFloatError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(IoError),
+    Parse(ParseFloatError),
+    NoSensor,
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(ref io) => io.description(),
+            Error::Parse(ref p) => p.description(),
+            Error::NoSensor => "No ambient light sensor found",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Io(ref io) => Some(io),
+            Error::Parse(ref p) => Some(p),
+            Error::NoSensor => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), FmtError> {
+        fmt.write_str(self.description())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{
+        scratch_dir,
+        write,
+    };
+
+    #[test]
+    fn reads_processed_lux() {
+        let root = scratch_dir("light-input");
+        let iio_dir = root.join(IIO_DIR);
+        write(&iio_dir.join("iio:device0/in_illuminance_input"), "321.5\n");
+
+        let sensor = LightSensor::at(&iio_dir.join("iio:device0")).unwrap();
+        assert_eq!(sensor.name(), "iio:device0");
+        assert_eq!(sensor.lux().unwrap(), 321.5);
+    }
+
+    #[test]
+    fn scales_raw_readings() {
+        let root = scratch_dir("light-raw");
+        let iio_dir = root.join(IIO_DIR);
+        write(&iio_dir.join("iio:device0/in_illuminance_raw"), "100\n");
+        write(&iio_dir.join("iio:device0/in_illuminance_scale"), "0.5\n");
+        write(&iio_dir.join("iio:device0/in_illuminance_offset"), "10\n");
+
+        let sensor = LightSensor::at(&iio_dir.join("iio:device0")).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 55.0);
+
+        write(&iio_dir.join("iio:device0/in_illuminance_raw"), "300\n");
+        assert_eq!(sensor.lux().unwrap(), 155.0);
+    }
+
+    #[test]
+    fn finds_the_first_light_sensor() {
+        let root = scratch_dir("light-find");
+        let iio_dir = root.join(IIO_DIR);
+        write(&iio_dir.join("iio:device0/in_accel_x_raw"), "12\n");
+        write(&iio_dir.join("iio:device1/in_illuminance_raw"), "40\n");
+        write(&iio_dir.join("iio:device2/in_illuminance_input"), "80\n");
+
+        assert_eq!(LightSensor::find(&iio_dir, None).unwrap().name(), "iio:device1");
+        let named = LightSensor::find(&iio_dir, Some("iio:device2")).unwrap();
+        assert_eq!(named.lux().unwrap(), 80.0);
+    }
+
+    #[test]
+    fn reports_missing_sensors() {
+        let root = scratch_dir("light-none");
+        let iio_dir = root.join(IIO_DIR);
+        write(&iio_dir.join("iio:device0/in_accel_x_raw"), "12\n");
+
+        match LightSensor::find(&iio_dir, None) {
+            Err(Error::NoSensor) => {},
+            other => panic!("expected no sensor, got {:?}", other.map(|s| s.name().to_string())),
+        }
+        assert!(LightSensor::find(&iio_dir, Some("iio:device0")).is_err());
+    }
+
+    #[test]
+    fn rejects_garbled_readings() {
+        let root = scratch_dir("light-garbled");
+        let iio_dir = root.join(IIO_DIR);
+        write(&iio_dir.join("iio:device0/in_illuminance_input"), "dark\n");
+
+        let sensor = LightSensor::at(&iio_dir.join("iio:device0")).unwrap();
+        assert!(sensor.lux().is_err());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod volume;
 pub mod brightness;
+pub mod light;
+pub mod color;
+pub mod night;
+pub mod sysfs;
 
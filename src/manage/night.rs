@@ -0,0 +1,327 @@
+extern crate libc;
+
+use std::f64::consts::PI;
+use std::io::Error as IoError;
+use std::str::FromStr;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use manage::color::NEUTRAL;
+
+pub const DEFAULT_NIGHT: f64 = 3500.0;
+pub const DEFAULT_DAWN: &'static str = "07:00";
+pub const DEFAULT_DUSK: &'static str = "20:00";
+/// Minutes taken to move between the day and night temperatures.
+pub const DEFAULT_TRANSITION: f64 = 30.0;
+
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// A local time of day, in seconds since midnight.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOfDay(i64);
+
+impl FromStr for TimeOfDay {
+    type Err = ();
+
+    /// Parses `HH:MM`.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut parts = s.trim().split(':');
+        let hours: i64 = try!(parts.next().and_then(|h| h.parse().ok()).ok_or(()));
+        let minutes: i64 = try!(parts.next().and_then(|m| m.parse().ok()).ok_or(()));
+        if parts.next().is_some() || hours < 0 || hours > 23 || minutes < 0 || minutes > 59 {
+            return Err(());
+        }
+        Ok(TimeOfDay(hours * 3600 + minutes * 60))
+    }
+}
+
+/// When the day starts and ends.
+#[derive(Debug, Clone, Copy)]
+pub enum Times {
+    /// The same local times every day. A dusk at or before dawn falls on
+    /// the next day, so the day can run past midnight.
+    Fixed(TimeOfDay, TimeOfDay),
+    /// Sunrise and sunset at a latitude and longitude, in degrees north
+    /// and east.
+    Solar(f64, f64),
+}
+
+/// Where the sun is over a given day.
+enum Daylight {
+    /// Sunrise and sunset, as Unix times.
+    Between(i64, i64),
+    PolarDay,
+    PolarNight,
+}
+
+/// Moves between a day and a night colour temperature, fading over
+/// `transition` centred on dawn and dusk.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    times: Times,
+    day: f64,
+    night: f64,
+    transition: i64,
+}
+
+impl Schedule {
+    pub fn new(times: Times) -> Self {
+        Schedule {
+            times: times,
+            day: NEUTRAL,
+            night: DEFAULT_NIGHT,
+            transition: (DEFAULT_TRANSITION * 60.0) as i64,
+        }
+    }
+
+    pub fn day(mut self, kelvin: f64) -> Self {
+        self.day = kelvin;
+        self
+    }
+
+    pub fn night(mut self, kelvin: f64) -> Self {
+        self.night = kelvin;
+        self
+    }
+
+    pub fn transition(mut self, minutes: f64) -> Self {
+        self.transition = (minutes.max(0.0) * 60.0) as i64;
+        self
+    }
+
+    /// The temperature for right now, by the wall clock and the local time
+    /// zone. Being worked out from scratch each time keeps it right across
+    /// suspend and clock changes.
+    pub fn now(&self) -> Result<f64, IoError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        self.at(now, &local_offset)
+    }
+
+    /// The temperature at Unix time `time`, in the time zone `zone(t)`
+    /// seconds east of UTC at each Unix time `t`. Yesterday's day is looked
+    /// at too, in case it runs past midnight.
+    fn at<Z>(&self, time: i64, zone: &Z) -> Result<f64, IoError>
+        where Z: Fn(i64) -> Result<i64, IoError>
+    {
+        let today = (time + try!(zone(time))).div_euclid(DAY_SECONDS);
+
+        let half = self.transition / 2;
+        // How far into the day it is, from 0 at night to 1 at day.
+        let progress = |start: i64| -> f64 {
+            if self.transition == 0 {
+                return if time >= start { 1.0 } else { 0.0 };
+            }
+            ((time - start + half) as f64 / self.transition as f64).max(0.0).min(1.0)
+        };
+        let mut daylight = 0f64;
+        for day in &[today - 1, today] {
+            daylight = daylight.max(match try!(self.daylight(*day, zone)) {
+                Daylight::Between(dawn, dusk) => progress(dawn) - progress(dusk),
+                Daylight::PolarDay => 1.0,
+                Daylight::PolarNight => 0.0,
+            });
+        }
+
+        Ok(self.night + (self.day - self.night) * daylight)
+    }
+
+    /// Where the sun is on the local `day`, counted from 1970-01-01.
+    fn daylight<Z>(&self, day: i64, zone: &Z) -> Result<Daylight, IoError>
+        where Z: Fn(i64) -> Result<i64, IoError>
+    {
+        match self.times {
+            Times::Fixed(TimeOfDay(dawn), TimeOfDay(dusk)) => {
+                let dusk_day = if dusk <= dawn { day + 1 } else { day };
+                Ok(Daylight::Between(try!(local_time(day, dawn, zone)),
+                                     try!(local_time(dusk_day, dusk, zone))))
+            },
+            Times::Solar(latitude, longitude) => {
+                let noon = try!(local_time(day, DAY_SECONDS / 2, zone));
+                Ok(sun_times(noon, latitude, longitude, try!(zone(noon))))
+            },
+        }
+    }
+}
+
+/// Sunrise and sunset on the local day containing `noon`, using the
+/// sunrise equation with the usual corrections for refraction and the
+/// size of the sun's disc.
+fn sun_times(noon: i64, latitude: f64, longitude: f64, offset: i64) -> Daylight {
+    let radians = PI / 180.0;
+
+    let julian_noon = (noon + offset) as f64 / DAY_SECONDS as f64 + 2440587.5;
+    let day = (julian_noon - 2451545.0 + 0.0008).round();
+    let mean_noon = day - longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_noon) % 360.0;
+    let center = 1.9148 * (anomaly * radians).sin()
+        + 0.02 * (2.0 * anomaly * radians).sin()
+        + 0.0003 * (3.0 * anomaly * radians).sin();
+    let ecliptic = (anomaly + center + 180.0 + 102.9372) % 360.0;
+    let transit = 2451545.0 + mean_noon
+        + 0.0053 * (anomaly * radians).sin()
+        - 0.0069 * (2.0 * ecliptic * radians).sin();
+
+    let declination = ((ecliptic * radians).sin() * (23.4397 * radians).sin()).asin();
+    let latitude = latitude * radians;
+    let cos_hour = ((-0.833 * radians).sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+
+    if cos_hour > 1.0 {
+        return Daylight::PolarNight;
+    }
+    if cos_hour < -1.0 {
+        return Daylight::PolarDay;
+    }
+
+    let hour = cos_hour.acos() / radians / 360.0;
+    let to_unix = |julian: f64| ((julian - 2440587.5) * DAY_SECONDS as f64) as i64;
+    Daylight::Between(to_unix(transit - hour), to_unix(transit + hour))
+}
+
+/// The Unix time `seconds` of the wall clock into the local `day`. Uses
+/// the offset in effect at that time rather than adding to midnight, so a
+/// day that gains or loses an hour to daylight saving still has dawn and
+/// dusk at the times on the clock.
+fn local_time<Z>(day: i64, seconds: i64, zone: &Z) -> Result<i64, IoError>
+    where Z: Fn(i64) -> Result<i64, IoError>
+{
+    let wall = day * DAY_SECONDS + seconds;
+    // The offset at the wall clock time read as UTC can be the one from
+    // before a change, so look again from the first guess.
+    let guess = wall - try!(zone(wall));
+    Ok(wall - try!(zone(guess)))
+}
+
+/// Seconds east of UTC in the local time zone at Unix time `time`.
+fn local_offset(time: i64) -> Result<i64, IoError> {
+    let time = time as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = ::std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return Err(IoError::last_os_error());
+        }
+        Ok(tm.tm_gmtoff as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-31 01:00 UTC, when the UK goes forward to BST.
+    const SPRING_FORWARD_UTC: i64 = 1711846800;
+    /// 2024-10-27, when the UK goes back to GMT at 02:00 BST.
+    const FALL_BACK_MIDNIGHT_UTC: i64 = 1729987200;
+    /// 2024-06-21.
+    const MIDSUMMER_MIDNIGHT_UTC: i64 = 1718928000;
+    const HOUR: i64 = 3600;
+
+    /// UK time in 2024, which has daylight saving.
+    fn london(time: i64) -> Result<i64, IoError> {
+        if time >= SPRING_FORWARD_UTC && time < FALL_BACK_MIDNIGHT_UTC + HOUR {
+            Ok(HOUR)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn fixed(dawn: &str, dusk: &str) -> Schedule {
+        Schedule::new(Times::Fixed(dawn.parse().unwrap(), dusk.parse().unwrap()))
+            .day(6500.0)
+            .night(3500.0)
+            .transition(0.0)
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!("07:30".parse::<TimeOfDay>().map(|t| t.0), Ok(7 * 3600 + 30 * 60));
+        assert_eq!(" 0:00 ".parse::<TimeOfDay>().map(|t| t.0), Ok(0));
+        for bad in &["24:00", "12:60", "7", "07:30:00", "-1:00", "noon"] {
+            assert!(bad.parse::<TimeOfDay>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn switches_at_dawn_and_dusk() {
+        let schedule = fixed("07:00", "20:00");
+        // Midsummer is in BST, an hour ahead of UTC.
+        let day = MIDSUMMER_MIDNIGHT_UTC;
+        assert_eq!(schedule.at(day + 6 * HOUR - 1, &london).unwrap(), 3500.0);
+        assert_eq!(schedule.at(day + 6 * HOUR, &london).unwrap(), 6500.0);
+        assert_eq!(schedule.at(day + 19 * HOUR - 1, &london).unwrap(), 6500.0);
+        assert_eq!(schedule.at(day + 19 * HOUR, &london).unwrap(), 3500.0);
+    }
+
+    #[test]
+    fn fades_across_the_transition() {
+        let schedule = fixed("07:00", "20:00").transition(60.0);
+        let dawn = MIDSUMMER_MIDNIGHT_UTC + 6 * HOUR;
+        assert_eq!(schedule.at(dawn - HOUR / 2, &london).unwrap(), 3500.0);
+        assert_eq!(schedule.at(dawn, &london).unwrap(), 5000.0);
+        assert_eq!(schedule.at(dawn + HOUR / 2, &london).unwrap(), 6500.0);
+    }
+
+    #[test]
+    fn dusk_before_dawn_falls_on_the_next_day() {
+        let schedule = fixed("07:00", "01:00");
+        let day = MIDSUMMER_MIDNIGHT_UTC - HOUR;
+        assert_eq!(schedule.at(day + HOUR / 2, &london).unwrap(), 6500.0);
+        assert_eq!(schedule.at(day + 3 * HOUR / 2, &london).unwrap(), 3500.0);
+        assert_eq!(schedule.at(day + 12 * HOUR, &london).unwrap(), 6500.0);
+        assert_eq!(schedule.at(day + 23 * HOUR, &london).unwrap(), 6500.0);
+    }
+
+    #[test]
+    fn keeps_clock_times_across_daylight_saving() {
+        // Dawn is at 03:00 GMT, after the clocks go back, so at 01:30 BST
+        // the four hour fade hasn't started yet.
+        let schedule = fixed("03:00", "20:00").transition(240.0);
+        assert_eq!(schedule.at(FALL_BACK_MIDNIGHT_UTC + HOUR / 2, &london).unwrap(), 3500.0);
+        assert_eq!(schedule.at(FALL_BACK_MIDNIGHT_UTC + 3 * HOUR, &london).unwrap(), 5000.0);
+        // Dusk is at 20:00 GMT.
+        assert_eq!(schedule.at(FALL_BACK_MIDNIGHT_UTC + 20 * HOUR, &london).unwrap(), 5000.0);
+    }
+
+    #[test]
+    fn fails_without_a_time_zone() {
+        let broken = |_| Err(IoError::from_raw_os_error(75));
+        assert!(fixed("07:00", "20:00").at(MIDSUMMER_MIDNIGHT_UTC, &broken).is_err());
+    }
+
+    #[test]
+    fn finds_sunrise_and_sunset() {
+        // London at midsummer: sunrise about 03:43 UTC, sunset about 20:21.
+        let noon = MIDSUMMER_MIDNIGHT_UTC + 11 * HOUR;
+        match sun_times(noon, 51.5, -0.13, london(noon).unwrap()) {
+            Daylight::Between(rise, set) => {
+                let minutes = |t: i64| (t - MIDSUMMER_MIDNIGHT_UTC) / 60;
+                assert!((minutes(rise) - (3 * 60 + 43)).abs() <= 5, "sunrise {}", minutes(rise));
+                assert!((minutes(set) - (20 * 60 + 21)).abs() <= 5, "sunset {}", minutes(set));
+            },
+            _ => panic!("no sunrise in London"),
+        }
+    }
+
+    #[test]
+    fn knows_polar_day_and_night() {
+        let solar = Schedule::new(Times::Solar(69.65, 18.96)).day(6500.0).night(3500.0);
+        match sun_times(MIDSUMMER_MIDNIGHT_UTC + 12 * HOUR, 69.65, 18.96, 0) {
+            Daylight::PolarDay => {},
+            _ => panic!("the sun sets in Tromsø at midsummer"),
+        }
+        assert_eq!(solar.at(MIDSUMMER_MIDNIGHT_UTC, &london).unwrap(), 6500.0);
+
+        // 2024-12-21.
+        let midwinter = MIDSUMMER_MIDNIGHT_UTC + 183 * 24 * HOUR;
+        match sun_times(midwinter + 12 * HOUR, 69.65, 18.96, 0) {
+            Daylight::PolarNight => {},
+            _ => panic!("the sun rises in Tromsø at midwinter"),
+        }
+        assert_eq!(solar.at(midwinter + 12 * HOUR, &london).unwrap(), 3500.0);
+    }
+}
@@ -0,0 +1,30 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Where sysfs is mounted, for containers with a bind-mounted `/sys` and
+/// for running against a fabricated tree.
+pub const ROOT_ENV: &'static str = "SYSHOOKS_SYSFS_ROOT";
+pub const DEFAULT_ROOT: &'static str = "/sys";
+
+/// `$SYSHOOKS_SYSFS_ROOT`, else `configured` (the config file's `root`),
+/// else `/sys`.
+pub fn root(configured: Option<&str>) -> PathBuf {
+    match env::var_os(ROOT_ENV) {
+        Some(ref root) if !root.is_empty() => PathBuf::from(root),
+        _ => PathBuf::from(configured.unwrap_or(DEFAULT_ROOT)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_the_configured_root_then_sys() {
+        if env::var_os(ROOT_ENV).is_some() {
+            return;
+        }
+        assert_eq!(root(Some("/tmp/sys")), PathBuf::from("/tmp/sys"));
+        assert_eq!(root(None), PathBuf::from(DEFAULT_ROOT));
+    }
+}
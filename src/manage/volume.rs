@@ -1,7 +1,12 @@
 extern crate alsa_sys as alsa;
+extern crate libc;
 
 use std::ptr;
-use std::ffi::CString;
+use std::ffi::{
+    CStr,
+    CString,
+};
+use std::os::raw::c_int;
 
 use self::alsa::{
     snd_mixer_t,
@@ -18,74 +23,182 @@ use self::alsa::{
     snd_mixer_elem_t,
     snd_mixer_selem_id_free,
     snd_mixer_selem_get_playback_volume_range,
-    snd_mixer_selem_set_playback_volume_all,
     snd_mixer_selem_is_playback_mono,
     snd_mixer_selem_set_playback_switch_all,
     snd_mixer_selem_has_playback_switch,
     snd_mixer_selem_get_playback_switch,
     snd_mixer_selem_get_playback_volume,
+    snd_mixer_selem_has_playback_volume,
+    snd_mixer_selem_get_name,
+    snd_mixer_selem_get_index,
+    snd_mixer_first_elem,
+    snd_mixer_elem_next,
+    snd_mixer_selem_has_capture_volume,
+    snd_mixer_selem_has_capture_switch,
+    snd_mixer_selem_get_capture_volume_range,
+    snd_mixer_selem_get_capture_volume,
+    snd_mixer_selem_set_capture_volume,
+    snd_mixer_selem_get_capture_switch,
+    snd_mixer_selem_set_capture_switch_all,
+    snd_mixer_selem_get_playback_dB_range,
+    snd_mixer_selem_get_playback_dB,
+    snd_mixer_selem_set_playback_dB,
+    snd_mixer_selem_set_playback_volume,
+    snd_mixer_selem_get_capture_dB_range,
+    snd_mixer_selem_is_capture_mono,
+    snd_mixer_selem_has_playback_channel,
+    snd_mixer_selem_has_capture_channel,
+    snd_mixer_selem_channel_name,
+    snd_card_next,
+    snd_card_get_name,
     SND_MIXER_SCHN_MONO,
+    SND_MIXER_SCHN_FRONT_LEFT,
+    SND_MIXER_SCHN_FRONT_RIGHT,
+    SND_MIXER_SCHN_REAR_LEFT,
+    SND_MIXER_SCHN_REAR_RIGHT,
+    SND_MIXER_SCHN_SIDE_LEFT,
+    SND_MIXER_SCHN_SIDE_RIGHT,
+    SND_MIXER_SCHN_REAR_CENTER,
 };
 
+/// Elements tried, in order, when none is named.
+pub const FALLBACK_ELEMENTS: [&'static str; 3] = ["Master", "PCM", "Speaker"];
+pub const CAPTURE_FALLBACK_ELEMENTS: [&'static str; 3] = ["Capture", "Mic", "Internal Mic"];
+
 pub struct Mixer {
     handle: *mut snd_mixer_t,
     elem: *mut snd_mixer_elem_t,
+    card: String,
+    name: String,
+    index: u32,
+    scale: Scale,
+    /// The balance channel levels are worked out from, once known.
+    balance: Option<f32>,
+}
+
+/// How fractions of the volume map onto an element's controls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    /// Linear in raw steps.
+    Raw,
+    /// alsamixer's mapping: linear in dB over small ranges, otherwise a
+    /// cubic curve on amplitude, so equal steps sound about equal. Elements
+    /// without a dB range still use raw steps.
+    Db,
 }
 
+/// dB ranges up to this wide are mapped linearly, in hundredths of a dB.
+const MAX_LINEAR_DB_SCALE: i64 = 24 * 100;
+/// What ALSA reports as the dB value of a control's muted minimum.
+const DB_GAIN_MUTE: i64 = -9999999;
+
 #[allow(dead_code)]
 impl Mixer {
-    pub fn new(card: &str, name: &str) -> Result<Self, AlsaError> {
+    /// Opens the simple element `name` with index `index` on `card`.
+    pub fn new(card: &str, name: &str, index: u32) -> Result<Self, AlsaError> {
+        let handle = try!(open_handle(card));
 
-        let card = match CString::new(card) {
-            Ok(c) => c,
-            Err(_) => return Err(AlsaError::CardStrContainsNull),
-        };
-        let name = match CString::new(name) {
-            Ok(n) => n,
-            Err(_) => return Err(AlsaError::NameStrContainsNull),
-        };
+        match find_selem(handle, name, index) {
+            Ok(element) => Ok(Mixer {
+                handle: handle,
+                elem: element,
+                card: card.to_string(),
+                name: name.to_string(),
+                index: index,
+                scale: Scale::Raw,
+                balance: None,
+            }),
+            Err(e) => {
+                unsafe {
+                    snd_mixer_close(handle);
+                }
+                Err(e)
+            },
+        }
+    }
 
-        // Load the handle
-        let mut handle: *mut snd_mixer_t = ptr::null_mut();
-        unsafe {
-            if snd_mixer_open(&mut handle, 0) != 0 {
-                return Err(AlsaError::MixerOpen);
-            }
-            if snd_mixer_attach(handle, card.as_ptr()) != 0 {
-                return Err(AlsaError::MixerAttach);
-            }
-            if snd_mixer_selem_register(handle, ptr::null_mut(), ptr::null_mut()) != 0 {
-                return Err(AlsaError::MixerRegister);
-            }
-            if snd_mixer_load(handle) != 0 {
-                return Err(AlsaError::MixerLoad);
-            }
+    /// Opens `name` on `card` if it is given. Otherwise uses the first of
+    /// `FALLBACK_ELEMENTS` the card has with a playback volume, or failing
+    /// that its first element with one, so cards without a `Master` work
+    /// too and a `Master` that is only a mute switch is passed over.
+    pub fn find(card: &str, name: Option<&str>, index: u32) -> Result<Self, AlsaError> {
+        Mixer::find_with(card, name, index, &FALLBACK_ELEMENTS,
+                         snd_mixer_selem_has_playback_volume)
+    }
+
+    /// Like `find`, but for the microphone: tries those of
+    /// `CAPTURE_FALLBACK_ELEMENTS` with a capture volume, passing over a
+    /// `Capture` that is only a switch, and then the first element with one.
+    pub fn find_capture(card: &str, name: Option<&str>, index: u32) -> Result<Self, AlsaError> {
+        Mixer::find_with(card, name, index, &CAPTURE_FALLBACK_ELEMENTS,
+                         snd_mixer_selem_has_capture_volume)
+    }
+
+    fn find_with(card: &str, name: Option<&str>, index: u32, fallbacks: &[&str],
+                 has_volume: unsafe extern "C" fn(*mut snd_mixer_elem_t) -> c_int)
+        -> Result<Self, AlsaError>
+    {
+        if let Some(n) = name {
+            return Mixer::new(card, n, index);
         }
 
-        // Find the element
-        let mut id: *mut snd_mixer_selem_id_t = ptr::null_mut();
-        let element = unsafe {
-            snd_mixer_selem_id_malloc(&mut id);
-            snd_mixer_selem_id_set_index(id, 0);
-            snd_mixer_selem_id_set_name(id, name.as_ptr());
-            snd_mixer_find_selem(handle, id)
+        let handle = try!(open_handle(card));
+
+        let fallback = fallbacks.iter()
+            .filter_map(|n| find_selem(handle, n, index).ok())
+            .filter(|e| unsafe { has_volume(*e) != 0 })
+            .next();
+        let element = match fallback {
+            Some(e) => e,
+            None => unsafe {
+                let mut elem = snd_mixer_first_elem(handle);
+                while !elem.is_null() && has_volume(elem) == 0 {
+                    elem = snd_mixer_elem_next(elem);
+                }
+                elem
+            },
         };
 
         if element.is_null() {
+            unsafe {
+                snd_mixer_close(handle);
+            }
             return Err(AlsaError::MixerFindSelem);
         }
 
-        // Clean up the ID
-        unsafe {
-            snd_mixer_selem_id_free(id);
-        }
+        let (name, index) = unsafe {
+            (CStr::from_ptr(snd_mixer_selem_get_name(element)).to_string_lossy().into_owned(),
+             snd_mixer_selem_get_index(element))
+        };
 
         Ok(Mixer {
             handle: handle,
             elem: element,
+            card: card.to_string(),
+            name: name,
+            index: index,
+            scale: Scale::Raw,
+            balance: None,
         })
     }
 
+    pub fn card(&self) -> &str {
+        &self.card
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Uses `scale` for `volume`, `set_volume` and `change_volume_clip`.
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
     pub fn volume_range(&self) -> (i64, i64) {
         let min: *mut i64 = &mut 0;
         let max: *mut i64 = &mut 0;
@@ -99,61 +212,132 @@ impl Mixer {
         }
     }
 
-    pub fn set_volume_raw(&self, volume: i64) {
-        unsafe {
-            snd_mixer_selem_set_playback_volume_all(self.elem, volume);
-        }
+    pub fn set_volume(&self, volume: f32) {
+        self.scale_volume(volume, 0);
     }
 
-    fn vol_raw_to_perc(&self, raw: i64) -> (f32, i64, i64) {
-        let (min, max) = self.volume_range();
-        ((raw - min) as f32 / (max - min) as f32, min, max)
+    /// The volume of the loudest channel.
+    pub fn volume(&self) -> f32 {
+        self.channels().iter()
+            .map(|c| self.volume_of(c.id))
+            .fold(0.0, f32::max)
     }
 
-    fn vol_perc_to_raw(&self, perc: f32) -> (i64, i64, i64) {
-        let (min, max) = self.volume_range();
-        (((max - min) as f32 * perc + min as f32) as i64, min, max)
+    pub fn change_volume_clip(&self, delta: f32) {
+        // Round away from the current volume so small steps always move.
+        let volume = (self.volume() + delta).max(0.0).min(1.0);
+        self.scale_volume(volume, if delta < 0.0 { -1 } else { 1 });
     }
 
-    pub fn set_volume(&self, volume: f32) {
-        let (vol, _, _) = self.vol_perc_to_raw(volume);
-        self.set_volume_raw(vol as i64);
+    /// The playback channels, or just `Mono`.
+    pub fn channels(&self) -> Vec<Channel> {
+        unsafe {
+            channels(snd_mixer_selem_is_playback_mono(self.elem) != 0,
+                     |c| snd_mixer_selem_has_playback_channel(self.elem, c) != 0)
+        }
     }
 
-    pub fn volume_raw(&self) -> i64 {
-        let vol: *mut i64 = &mut 0;
+    /// The volume of `channel` from 0 to 1.
+    pub fn volume_of(&self, channel: c_int) -> f32 {
+        if let Some(range) = self.db_range() {
+            return self.volume_db(channel, range);
+        }
+        let mut vol = 0;
         unsafe {
-            snd_mixer_selem_get_playback_volume(self.elem, SND_MIXER_SCHN_MONO, vol);
-            *vol
+            snd_mixer_selem_get_playback_volume(self.elem, channel, &mut vol);
         }
+        raw_to_volume(vol, self.volume_range())
     }
 
-    pub fn volume(&self) -> f32 {
-        let vol = self.volume_raw();
-        self.vol_raw_to_perc(vol).0
+    /// Sets `channel` to `volume` from 0 to 1, rounding up if `dir` is
+    /// positive and down if it is negative.
+    pub fn set_volume_of(&self, channel: c_int, volume: f32, dir: i32) {
+        if let Some(range) = self.db_range() {
+            return self.set_volume_db(channel, volume, range, dir);
+        }
+        let raw = volume_to_raw(volume, self.volume_range(), dir);
+        unsafe {
+            snd_mixer_selem_set_playback_volume(self.elem, channel, raw);
+        }
     }
 
-    pub fn change_volume_raw(&self, delta: i64) {
-        let vol = self.volume_raw();
-        self.set_volume_raw(vol + delta);
+    /// Sets every channel from `volume` and the balance, the one given to
+    /// `keep_balance` or `set_balance` or else the one the channels have
+    /// now.
+    fn scale_volume(&self, volume: f32, dir: i32) {
+        let balance = self.balance.unwrap_or_else(|| self.balance());
+        for channel in self.channels() {
+            self.set_volume_of(channel.id, channel_level(volume, side(channel.id), balance), dir);
+        }
     }
 
-    pub fn change_volume(&self, delta: f32) {
-        let (del, _, _) = self.vol_perc_to_raw(delta);
-        self.change_volume_raw(del);
+    /// Works out channel levels from `balance`, e.g. one saved earlier,
+    /// from now on, so it survives the volume reaching zero.
+    pub fn keep_balance(&mut self, balance: f32) {
+        if !self.is_mono() {
+            self.balance = Some(balance.max(-100.0).min(100.0));
+        }
     }
 
-    pub fn change_volume_clip(&self, delta: f32) {
-        let (del, min, max) = self.vol_perc_to_raw(delta);
-        let curr = self.volume_raw();
-        let vol = if del + curr > max {
-            max
-        } else if del + curr < min {
-            min
-        } else {
-            curr + del
+    /// From -100 with only the left channels playing to 100 with only the
+    /// right ones: the balance given to `keep_balance` or `set_balance`, or
+    /// else the one the channels have. Mono elements are always centred.
+    pub fn balance(&self) -> f32 {
+        if self.is_mono() {
+            return 0.0;
+        }
+        if let Some(balance) = self.balance {
+            return balance;
+        }
+        let levels: Vec<_> = self.channels().iter()
+            .map(|c| (side(c.id), self.volume_of(c.id)))
+            .collect();
+        balance_of(&levels)
+    }
+
+    /// Turns down the left or right channels to `balance`, from -100 to
+    /// 100, leaving the louder side and centre channels at the volume, and
+    /// keeps it for later volume changes.
+    pub fn set_balance(&mut self, balance: f32) {
+        if self.is_mono() {
+            return;
+        }
+        self.keep_balance(balance);
+        let volume = self.volume();
+        self.scale_volume(volume, 0);
+    }
+
+    /// The playback dB range in hundredths of a dB, if the dB scale is in
+    /// use and the element has one.
+    fn db_range(&self) -> Option<(i64, i64)> {
+        if self.scale != Scale::Db {
+            return None;
+        }
+        let (mut min, mut max) = (0, 0);
+        let found = unsafe {
+            snd_mixer_selem_get_playback_dB_range(self.elem, &mut min, &mut max) == 0
         };
-        self.set_volume_raw(vol);
+        if found && min < max {
+            Some((min, max))
+        } else {
+            None
+        }
+    }
+
+    fn volume_db(&self, channel: c_int, range: (i64, i64)) -> f32 {
+        let mut value = 0;
+        unsafe {
+            snd_mixer_selem_get_playback_dB(self.elem, channel, &mut value);
+        }
+        db_to_volume(value, range) as f32
+    }
+
+    /// Sets `channel` to `volume` from 0 to 1 on the dB scale.
+    fn set_volume_db(&self, channel: c_int, volume: f32, range: (i64, i64), dir: i32) {
+        let value = volume_to_db(volume as f64, range, dir);
+        unsafe {
+            snd_mixer_selem_set_playback_dB(self.elem, channel, value, dir);
+        }
     }
 
     pub fn is_mono(&self) -> bool {
@@ -207,14 +391,107 @@ impl Mixer {
         }
     }
 
-    fn raw_is_muted(&self) -> bool {
-        let is_muted: *mut i32 = &mut 0;
+    pub fn capture_volume_range(&self) -> (i64, i64) {
+        let (mut min, mut max) = (0, 0);
+        unsafe {
+            snd_mixer_selem_get_capture_volume_range(self.elem, &mut min, &mut max);
+        }
+        (min, max)
+    }
+
+    /// The capture channels, or just `Mono`.
+    pub fn capture_channels(&self) -> Vec<Channel> {
+        unsafe {
+            channels(snd_mixer_selem_is_capture_mono(self.elem) != 0,
+                     |c| snd_mixer_selem_has_capture_channel(self.elem, c) != 0)
+        }
+    }
+
+    /// The capture volume of `channel` from 0 to 1.
+    pub fn capture_volume_of(&self, channel: c_int) -> f32 {
+        let mut vol = 0;
+        unsafe {
+            snd_mixer_selem_get_capture_volume(self.elem, channel, &mut vol);
+        }
+        raw_to_volume(vol, self.capture_volume_range())
+    }
+
+    /// Sets the capture volume of `channel` to `volume` from 0 to 1,
+    /// rounding up if `dir` is positive and down if it is negative.
+    pub fn set_capture_volume_of(&self, channel: c_int, volume: f32, dir: i32) {
+        let raw = volume_to_raw(volume, self.capture_volume_range(), dir);
+        unsafe {
+            snd_mixer_selem_set_capture_volume(self.elem, channel, raw);
+        }
+    }
+
+    /// The capture volume of the loudest channel.
+    pub fn capture_volume(&self) -> f32 {
+        self.capture_channels().iter()
+            .map(|c| self.capture_volume_of(c.id))
+            .fold(0.0, f32::max)
+    }
+
+    pub fn set_capture_volume(&self, volume: f32) {
+        self.scale_capture_volume(volume, 0);
+    }
+
+    pub fn change_capture_volume_clip(&self, delta: f32) {
+        // Round away from the current volume so small steps always move.
+        let volume = (self.capture_volume() + delta).max(0.0).min(1.0);
+        self.scale_capture_volume(volume, if delta < 0.0 { -1 } else { 1 });
+    }
+
+    fn scale_capture_volume(&self, volume: f32, dir: i32) {
+        for channel in self.capture_channels() {
+            self.set_capture_volume_of(channel.id, volume, dir);
+        }
+    }
+
+    pub fn can_mute_capture(&self) -> bool {
         unsafe {
-            snd_mixer_selem_get_playback_switch(self.elem, SND_MIXER_SCHN_MONO, is_muted);
-            *is_muted == 0
+            snd_mixer_selem_has_capture_switch(self.elem) != 0
         }
     }
 
+    /// Whether the capture switch is off on every channel. Elements
+    /// without one are never muted.
+    pub fn is_capture_muted(&self) -> bool {
+        if !self.can_mute_capture() {
+            return false;
+        }
+        self.capture_channels().iter().all(|c| {
+            let mut on = 0;
+            unsafe {
+                snd_mixer_selem_get_capture_switch(self.elem, c.id, &mut on);
+            }
+            on == 0
+        })
+    }
+
+    pub fn toggle_capture_mute(&self) -> Result<(), ()> {
+        if self.can_mute_capture() {
+            let on = if self.is_capture_muted() { 1 } else { 0 };
+            unsafe {
+                snd_mixer_selem_set_capture_switch_all(self.elem, on);
+            }
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Whether the playback switch is off on every channel.
+    fn raw_is_muted(&self) -> bool {
+        self.channels().iter().all(|c| {
+            let mut on = 0;
+            unsafe {
+                snd_mixer_selem_get_playback_switch(self.elem, c.id, &mut on);
+            }
+            on == 0
+        })
+    }
+
     fn raw_unmute(&self) {
         unsafe {
             snd_mixer_selem_set_playback_switch_all(self.elem, 1);
@@ -228,6 +505,364 @@ impl Mixer {
     }
 }
 
+/// A sound card and its simple mixer elements.
+#[derive(Debug, Clone)]
+pub struct Card {
+    /// The ALSA name to open it by, e.g. `hw:0`.
+    pub id: String,
+    pub name: String,
+    pub elements: Vec<Element>,
+}
+
+/// A simple mixer element, e.g. `Master` with index 0.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub name: String,
+    pub index: u32,
+    pub playback: Option<Controls>,
+    pub capture: Option<Controls>,
+}
+
+/// A channel of an element, e.g. `Front Left`.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub id: c_int,
+    pub name: String,
+}
+
+/// What an element can do in one direction.
+#[derive(Debug, Clone)]
+pub struct Controls {
+    /// Raw volume range, if the element has a volume.
+    pub volume: Option<(i64, i64)>,
+    /// Volume range in dB, if the driver reports one.
+    pub db: Option<(f64, f64)>,
+    pub switch: bool,
+    /// Channel names, or just `Mono`.
+    pub channels: Vec<String>,
+}
+
+impl Card {
+    /// The card and its elements as a JSON object, for `volume list --json`.
+    pub fn to_json(&self) -> String {
+        let elements: Vec<String> = self.elements.iter().map(|e| {
+            format!(r#"{{"name":{},"index":{},"playback":{},"capture":{}}}"#,
+                    json_string(&e.name), e.index,
+                    controls_json(&e.playback), controls_json(&e.capture))
+        }).collect();
+        format!(r#"{{"id":{},"name":{},"elements":[{}]}}"#,
+                json_string(&self.id), json_string(&self.name), elements.join(","))
+    }
+}
+
+fn controls_json(controls: &Option<Controls>) -> String {
+    let controls = match *controls {
+        Some(ref c) => c,
+        None => return "null".to_string(),
+    };
+    let channels: Vec<String> = controls.channels.iter().map(|c| json_string(c)).collect();
+    format!(r#"{{"volume":{},"dB":{},"switch":{},"channels":[{}]}}"#,
+            controls.volume.map(|(min, max)| format!("[{},{}]", min, max))
+                .unwrap_or("null".to_string()),
+            controls.db.map(|(min, max)| format!("[{},{}]", min, max))
+                .unwrap_or("null".to_string()),
+            controls.switch,
+            channels.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Lists every sound card with its mixer elements.
+pub fn cards() -> Result<Vec<Card>, AlsaError> {
+    let mut cards = Vec::new();
+    let mut index = -1;
+    loop {
+        if unsafe { snd_card_next(&mut index) } != 0 {
+            return Err(AlsaError::CardNext);
+        }
+        if index < 0 {
+            break;
+        }
+
+        let name = unsafe {
+            let mut name = ptr::null_mut();
+            if snd_card_get_name(index, &mut name) == 0 && !name.is_null() {
+                let owned = CStr::from_ptr(name).to_string_lossy().into_owned();
+                libc::free(name as *mut libc::c_void);
+                owned
+            } else {
+                String::new()
+            }
+        };
+
+        let id = format!("hw:{}", index);
+        cards.push(Card {
+            elements: try!(elements(&id)),
+            id: id,
+            name: name,
+        });
+    }
+    Ok(cards)
+}
+
+/// Lists the simple mixer elements of `card`.
+pub fn elements(card: &str) -> Result<Vec<Element>, AlsaError> {
+    let handle = try!(open_handle(card));
+    let mut elements = Vec::new();
+
+    unsafe {
+        let mut elem = snd_mixer_first_elem(handle);
+        while !elem.is_null() {
+            let playback = snd_mixer_selem_has_playback_volume(elem) != 0
+                || snd_mixer_selem_has_playback_switch(elem) != 0;
+            let capture = snd_mixer_selem_has_capture_volume(elem) != 0
+                || snd_mixer_selem_has_capture_switch(elem) != 0;
+
+            elements.push(Element {
+                name: CStr::from_ptr(snd_mixer_selem_get_name(elem)).to_string_lossy().into_owned(),
+                index: snd_mixer_selem_get_index(elem),
+                playback: if playback { Some(playback_controls(elem)) } else { None },
+                capture: if capture { Some(capture_controls(elem)) } else { None },
+            });
+            elem = snd_mixer_elem_next(elem);
+        }
+        snd_mixer_close(handle);
+    }
+
+    Ok(elements)
+}
+
+unsafe fn playback_controls(elem: *mut snd_mixer_elem_t) -> Controls {
+    let (mut min, mut max) = (0, 0);
+    let volume = if snd_mixer_selem_has_playback_volume(elem) != 0 {
+        snd_mixer_selem_get_playback_volume_range(elem, &mut min, &mut max);
+        Some((min, max))
+    } else {
+        None
+    };
+    let db = if snd_mixer_selem_get_playback_dB_range(elem, &mut min, &mut max) == 0 {
+        Some((min as f64 / 100.0, max as f64 / 100.0))
+    } else {
+        None
+    };
+    Controls {
+        volume: volume,
+        db: db,
+        switch: snd_mixer_selem_has_playback_switch(elem) != 0,
+        channels: channels(snd_mixer_selem_is_playback_mono(elem) != 0,
+                           |c| snd_mixer_selem_has_playback_channel(elem, c) != 0)
+            .into_iter().map(|c| c.name).collect(),
+    }
+}
+
+unsafe fn capture_controls(elem: *mut snd_mixer_elem_t) -> Controls {
+    let (mut min, mut max) = (0, 0);
+    let volume = if snd_mixer_selem_has_capture_volume(elem) != 0 {
+        snd_mixer_selem_get_capture_volume_range(elem, &mut min, &mut max);
+        Some((min, max))
+    } else {
+        None
+    };
+    let db = if snd_mixer_selem_get_capture_dB_range(elem, &mut min, &mut max) == 0 {
+        Some((min as f64 / 100.0, max as f64 / 100.0))
+    } else {
+        None
+    };
+    Controls {
+        volume: volume,
+        db: db,
+        switch: snd_mixer_selem_has_capture_switch(elem) != 0,
+        channels: channels(snd_mixer_selem_is_capture_mono(elem) != 0,
+                           |c| snd_mixer_selem_has_capture_channel(elem, c) != 0)
+            .into_iter().map(|c| c.name).collect(),
+    }
+}
+
+/// The channels `has` reports, from front left to rear centre.
+unsafe fn channels<F: Fn(c_int) -> bool>(mono: bool, has: F) -> Vec<Channel> {
+    if mono {
+        return vec![Channel {
+            id: SND_MIXER_SCHN_MONO,
+            name: "Mono".to_string(),
+        }];
+    }
+    (SND_MIXER_SCHN_FRONT_LEFT..SND_MIXER_SCHN_REAR_CENTER + 1)
+        .filter(|c| has(*c))
+        .map(|c| Channel {
+            id: c,
+            name: CStr::from_ptr(snd_mixer_selem_channel_name(c)).to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+/// -1 for left channels, 1 for right ones and 0 for the rest.
+fn side(channel: c_int) -> f32 {
+    match channel {
+        SND_MIXER_SCHN_FRONT_LEFT | SND_MIXER_SCHN_REAR_LEFT | SND_MIXER_SCHN_SIDE_LEFT => -1.0,
+        SND_MIXER_SCHN_FRONT_RIGHT | SND_MIXER_SCHN_REAR_RIGHT | SND_MIXER_SCHN_SIDE_RIGHT => 1.0,
+        _ => 0.0,
+    }
+}
+
+/// The balance of channels given as `(side, level)` pairs, from -100 with
+/// only the left ones playing to 100 with only the right ones.
+fn balance_of(levels: &[(f32, f32)]) -> f32 {
+    let (mut left, mut right) = (0f32, 0f32);
+    for &(side, level) in levels {
+        if side < 0.0 {
+            left = left.max(level);
+        } else if side > 0.0 {
+            right = right.max(level);
+        }
+    }
+    let top = left.max(right);
+    if top > 0.0 {
+        100.0 * (right - left) / top
+    } else {
+        0.0
+    }
+}
+
+/// Where `value`, in hundredths of a dB, sits from 0 to 1 in `[min, max]`
+/// on alsamixer's scale.
+fn db_to_volume(value: i64, (min, max): (i64, i64)) -> f64 {
+    if max - min <= MAX_LINEAR_DB_SCALE {
+        return (value - min) as f64 / (max - min) as f64;
+    }
+
+    let mut normalized = 10f64.powf((value - max) as f64 / 6000.0);
+    if min != DB_GAIN_MUTE {
+        let min_norm = 10f64.powf((min - max) as f64 / 6000.0);
+        normalized = (normalized - min_norm) / (1.0 - min_norm);
+    }
+    normalized.max(0.0).min(1.0)
+}
+
+/// The dB value, in hundredths of a dB, for `volume` from 0 to 1 on
+/// alsamixer's scale, rounded towards `dir`. Zero on a range that starts
+/// at mute is the mute itself rather than the log of zero.
+fn volume_to_db(volume: f64, (min, max): (i64, i64), dir: i32) -> i64 {
+    let volume = volume.max(0.0).min(1.0);
+    if max - min <= MAX_LINEAR_DB_SCALE {
+        return round_dir(volume * (max - min) as f64, dir) as i64 + min;
+    }
+    if volume == 0.0 && min == DB_GAIN_MUTE {
+        return min;
+    }
+
+    let mut volume = volume;
+    if min != DB_GAIN_MUTE {
+        let min_norm = 10f64.powf((min - max) as f64 / 6000.0);
+        volume = volume * (1.0 - min_norm) + min_norm;
+    }
+    (round_dir(6000.0 * volume.log10(), dir) as i64 + max).max(min).min(max)
+}
+
+/// The level of a channel on `side` (-1 left, 0 centre, 1 right) at
+/// `volume` and `balance`: the far side is turned down, the rest stay at
+/// the volume.
+fn channel_level(volume: f32, side: f32, balance: f32) -> f32 {
+    volume * (1.0 + side * balance / 100.0).max(0.0).min(1.0)
+}
+
+/// Where `raw` sits from 0 to 1 in `[min, max]`. An element whose range is
+/// a single value is always at 0.
+fn raw_to_volume(raw: i64, (min, max): (i64, i64)) -> f32 {
+    if max <= min {
+        return 0.0;
+    }
+    (raw - min) as f32 / (max - min) as f32
+}
+
+/// The raw value in `[min, max]` for `volume` from 0 to 1, rounded towards
+/// `dir`.
+fn volume_to_raw(volume: f32, (min, max): (i64, i64), dir: i32) -> i64 {
+    if max <= min {
+        return min;
+    }
+    (round_dir(volume as f64 * (max - min) as f64, dir) as i64 + min).max(min).min(max)
+}
+
+/// Rounds up if `dir` is positive, down if it is negative, and to the
+/// nearest otherwise.
+fn round_dir(value: f64, dir: i32) -> f64 {
+    if dir > 0 {
+        value.ceil()
+    } else if dir < 0 {
+        value.floor()
+    } else {
+        value.round()
+    }
+}
+
+/// Opens and loads the mixer of `card`.
+fn open_handle(card: &str) -> Result<*mut snd_mixer_t, AlsaError> {
+    let card = match CString::new(card) {
+        Ok(c) => c,
+        Err(_) => return Err(AlsaError::CardStrContainsNull),
+    };
+
+    let mut handle: *mut snd_mixer_t = ptr::null_mut();
+    unsafe {
+        if snd_mixer_open(&mut handle, 0) != 0 {
+            return Err(AlsaError::MixerOpen);
+        }
+        let error = if snd_mixer_attach(handle, card.as_ptr()) != 0 {
+            Some(AlsaError::MixerAttach)
+        } else if snd_mixer_selem_register(handle, ptr::null_mut(), ptr::null_mut()) != 0 {
+            Some(AlsaError::MixerRegister)
+        } else if snd_mixer_load(handle) != 0 {
+            Some(AlsaError::MixerLoad)
+        } else {
+            None
+        };
+        if let Some(e) = error {
+            snd_mixer_close(handle);
+            return Err(e);
+        }
+    }
+
+    Ok(handle)
+}
+
+/// Finds the simple element `name`,`index` on a loaded mixer.
+fn find_selem(handle: *mut snd_mixer_t, name: &str, index: u32)
+    -> Result<*mut snd_mixer_elem_t, AlsaError>
+{
+    let name = match CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(AlsaError::NameStrContainsNull),
+    };
+
+    let mut id: *mut snd_mixer_selem_id_t = ptr::null_mut();
+    let element = unsafe {
+        snd_mixer_selem_id_malloc(&mut id);
+        snd_mixer_selem_id_set_index(id, index);
+        snd_mixer_selem_id_set_name(id, name.as_ptr());
+        let element = snd_mixer_find_selem(handle, id);
+        snd_mixer_selem_id_free(id);
+        element
+    };
+
+    if element.is_null() {
+        Err(AlsaError::MixerFindSelem)
+    } else {
+        Ok(element)
+    }
+}
+
 impl Drop for Mixer {
     fn drop(&mut self) {
         unsafe {
@@ -245,4 +880,177 @@ pub enum AlsaError {
     MixerLoad,
     MixerRegister,
     MixerFindSelem,
+    CardNext,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_json_strings() {
+        assert_eq!(json_string("Master"), r#""Master""#);
+        assert_eq!(json_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+        assert_eq!(json_string("a\nb\u{1}"), r#""a\u000ab\u0001""#);
+        assert_eq!(json_string("Über"), "\"Über\"");
+    }
+
+    #[test]
+    fn writes_cards_as_json() {
+        let card = Card {
+            id: "hw:0".to_string(),
+            name: "HDA \"Intel\"".to_string(),
+            elements: vec![
+                Element {
+                    name: "Master".to_string(),
+                    index: 0,
+                    playback: Some(Controls {
+                        volume: Some((0, 87)),
+                        db: Some((-65.25, 0.0)),
+                        switch: true,
+                        channels: vec!["Front Left".to_string(), "Front Right".to_string()],
+                    }),
+                    capture: None,
+                },
+            ],
+        };
+        assert_eq!(card.to_json(),
+                   concat!(r#"{"id":"hw:0","name":"HDA \"Intel\"","elements":["#,
+                           r#"{"name":"Master","index":0,"#,
+                           r#""playback":{"volume":[0,87],"dB":[-65.25,0],"switch":true,"#,
+                           r#""channels":["Front Left","Front Right"]},"capture":null}]}"#));
+    }
+
+    #[test]
+    fn maps_small_db_ranges_linearly() {
+        let range = (-1200, 0);
+        assert_eq!(volume_to_db(0.5, range, 0), -600);
+        assert_eq!(volume_to_db(0.0, range, 0), -1200);
+        assert_eq!(db_to_volume(-600, range), 0.5);
+        assert_eq!(db_to_volume(-1200, range), 0.0);
+    }
+
+    #[test]
+    fn maps_large_db_ranges_on_a_cubic_like_curve() {
+        let range = (-6400, 0);
+        assert_eq!(volume_to_db(0.0, range, 0), -6400);
+        assert_eq!(volume_to_db(1.0, range, 0), 0);
+        for volume in &[0.1, 0.3, 0.5, 0.9] {
+            let db = volume_to_db(*volume, range, 0);
+            assert!((db_to_volume(db, range) - volume).abs() < 1e-3, "{} -> {}", volume, db);
+        }
+        // Half the volume is far less than half the dB range.
+        assert!(volume_to_db(0.5, range, 0) > -3200);
+    }
+
+    #[test]
+    fn maps_zero_onto_a_muted_minimum() {
+        let range = (DB_GAIN_MUTE, 0);
+        assert_eq!(volume_to_db(0.0, range, 0), DB_GAIN_MUTE);
+        assert_eq!(volume_to_db(0.0, range, -1), DB_GAIN_MUTE);
+        assert_eq!(volume_to_db(0.5, range, 0), -1806);
+        assert_eq!(db_to_volume(DB_GAIN_MUTE, range), 0.0);
+        assert!((db_to_volume(-1806, range) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn clamps_db_volumes_to_the_range() {
+        assert_eq!(volume_to_db(1.5, (-6400, 0), 1), 0);
+        assert_eq!(volume_to_db(-0.5, (-6400, 0), -1), -6400);
+        assert_eq!(volume_to_db(2.0, (-1200, 0), 0), 0);
+        assert_eq!(db_to_volume(100, (-6400, 0)), 1.0);
+    }
+
+    #[test]
+    fn rounds_db_values_towards_the_step() {
+        let range = (-6400, 0);
+        let exact = 6000.0 * (0.5f64 * (1.0 - 10f64.powf(-6400.0 / 6000.0))
+                              + 10f64.powf(-6400.0 / 6000.0)).log10();
+        assert_eq!(volume_to_db(0.5, range, 1), exact.ceil() as i64);
+        assert_eq!(volume_to_db(0.5, range, -1), exact.floor() as i64);
+    }
+
+    #[test]
+    fn rounds_raw_volume_steps_away_from_the_current_level() {
+        let range = (0, 31);
+        // One percent of 31 steps is less than one, but still moves a step.
+        let up = volume_to_raw(raw_to_volume(10, range) + 0.01, range, 1);
+        assert_eq!(up, 11);
+        let down = volume_to_raw(raw_to_volume(up, range) - 0.01, range, -1);
+        assert_eq!(down, 10);
+        assert_eq!(volume_to_raw(0.5, range, 0), 16);
+        assert_eq!(volume_to_raw(0.5, (-10, 21), 0), 6);
+        assert_eq!(volume_to_raw(1.5, range, 1), 31);
+        assert_eq!(volume_to_raw(-0.5, range, -1), 0);
+    }
+
+    #[test]
+    fn keeps_single_value_ranges_at_zero() {
+        assert_eq!(raw_to_volume(3, (3, 3)), 0.0);
+        assert_eq!(volume_to_raw(0.7, (3, 3), 1), 3);
+        assert_eq!(volume_to_raw(0.7, (3, 3), -1), 3);
+    }
+
+    #[test]
+    fn works_out_channel_levels_from_the_balance() {
+        assert_eq!(channel_level(0.8, -1.0, 0.0), 0.8);
+        assert_eq!(channel_level(0.8, 1.0, 0.0), 0.8);
+        // Leaning right turns the left side down and leaves the right and
+        // centre alone.
+        assert_eq!(channel_level(0.8, -1.0, 50.0), 0.4);
+        assert_eq!(channel_level(0.8, 1.0, 50.0), 0.8);
+        assert_eq!(channel_level(0.8, 0.0, 50.0), 0.8);
+        assert_eq!(channel_level(0.8, 1.0, -100.0), 0.0);
+        // Zero stays zero and comes back with the same balance.
+        assert_eq!(channel_level(0.0, -1.0, 50.0), 0.0);
+        assert_eq!(channel_level(0.6, -1.0, 50.0), 0.3);
+    }
+
+    #[test]
+    fn works_out_the_balance_from_channel_levels() {
+        assert_eq!(balance_of(&[(-1.0, 0.4), (1.0, 0.8)]), 50.0);
+        assert_eq!(balance_of(&[(-1.0, 0.8), (1.0, 0.2), (0.0, 1.0)]), -75.0);
+        assert_eq!(balance_of(&[(-1.0, 0.0), (1.0, 0.0)]), 0.0);
+        assert_eq!(balance_of(&[(0.0, 0.7)]), 0.0);
+    }
+
+    /// Steps a stereo element the way `change_volume_clip` does, or
+    /// rescales it like `set_balance` for no `delta`, through
+    /// `set_volume_of` and `volume_of` on raw values.
+    fn step(raw: &mut [(f32, i64)], range: (i64, i64), balance: f32, delta: f32) {
+        let volume = raw.iter()
+            .map(|&(_, r)| raw_to_volume(r, range))
+            .fold(0.0, f32::max);
+        let volume = (volume + delta).max(0.0).min(1.0);
+        let dir = if delta < 0.0 { -1 } else if delta > 0.0 { 1 } else { 0 };
+        for channel in raw.iter_mut() {
+            channel.1 = volume_to_raw(channel_level(volume, channel.0, balance), range, dir);
+        }
+    }
+
+    fn read_balance(raw: &[(f32, i64)], range: (i64, i64)) -> f32 {
+        let levels: Vec<_> = raw.iter().map(|&(side, r)| (side, raw_to_volume(r, range))).collect();
+        balance_of(&levels)
+    }
+
+    #[test]
+    fn keeps_the_balance_across_volume_steps() {
+        let range = (0, 87);
+        let mut raw = [(-1.0, 44), (1.0, 44)];
+        // set_balance at -50 turns the right side down to half.
+        step(&mut raw, range, -50.0, 0.0);
+        assert_eq!(raw, [(-1.0, 44), (1.0, 22)]);
+        for _ in 0..5 {
+            step(&mut raw, range, -50.0, 0.05);
+            assert!((read_balance(&raw, range) + 50.0).abs() < 3.0, "{:?}", raw);
+        }
+        // Down past zero and back up comes back with the same balance.
+        for _ in 0..30 {
+            step(&mut raw, range, -50.0, -0.05);
+        }
+        assert_eq!(raw, [(-1.0, 0), (1.0, 0)]);
+        step(&mut raw, range, -50.0, 0.5);
+        assert_eq!(raw, [(-1.0, 44), (1.0, 22)]);
+        assert!((read_balance(&raw, range) + 50.0).abs() < 3.0);
+    }
 }
@@ -15,13 +15,29 @@ pub fn show_brightness(percent: u32) -> Result<(), Error> {
         _ => "notification-display-brightness-full",
     };
 
+    show(icon, "brightness", percent)
+}
+
+pub fn show_kbd_brightness(percent: u32) -> Result<(), Error> {
+    let icon = match percent {
+        0 => "notification-keyboard-brightness-off",
+        x if x <= 33 => "notification-keyboard-brightness-low",
+        x if x <= 67 => "notification-keyboard-brightness-medium",
+        x if x <= 99 => "notification-keyboard-brightness-high",
+        _ => "notification-keyboard-brightness-full",
+    };
+
+    show(icon, "keyboard-brightness", percent)
+}
+
+fn show(icon: &str, synchronous: &str, percent: u32) -> Result<(), Error> {
     let _ = Notification::new()
         .summary(" ")
         .icon(icon)
         .hint(NotificationHint::CustomInt("value".to_string(), percent as i32))
         .hint(NotificationHint::Custom(
                 "x-canonical-private-synchronous".to_string(),
-                "brightness".to_string()))
+                synchronous.to_string()))
         .show()?;
     Ok(())
 }
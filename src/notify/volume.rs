@@ -35,3 +35,26 @@ pub fn show_volume(percent: Volume) -> Result<(), Error> {
         .show()?;
     Ok(())
 }
+
+pub fn show_mic(percent: Volume) -> Result<(), Error> {
+    let icon = match percent {
+        Volume::Muted => "notification-microphone-sensitivity-muted",
+        Volume::Percent(x) if x == 0 => "notification-microphone-sensitivity-off",
+        Volume::Percent(x) if x < 33 => "notification-microphone-sensitivity-low",
+        Volume::Percent(x) if x < 67 => "notification-microphone-sensitivity-medium",
+        _ => "notification-microphone-sensitivity-high",
+    };
+
+    let value = match percent {
+        Volume::Muted => 0,
+        Volume::Percent(p) => p,
+    };
+
+    let _ = Notification::new()
+        .summary(" ")
+        .icon(icon)
+        .hint(NotificationHint::Custom("synchronous".to_string(), "mic".to_string()))
+        .hint(NotificationHint::CustomInt("value".to_string(), value as i32))
+        .show()?;
+    Ok(())
+}
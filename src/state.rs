@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    Read,
+    Write,
+};
+use std::io::Error as IoError;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use config::xdg_dir;
+
+/// Values remembered between runs, kept as `key = value` lines in
+/// `$XDG_STATE_HOME/syshooks/state`.
+#[derive(Debug, Default)]
+pub struct State {
+    values: BTreeMap<String, String>,
+}
+
+impl State {
+    /// Reads the state file. A missing file is an empty state.
+    pub fn load() -> Result<Self, IoError> {
+        match state_path() {
+            Some(path) => State::load_from(&path),
+            None => Ok(State::default()),
+        }
+    }
+
+    /// Reads the state kept at `path`. A file that can't be opened is an
+    /// empty state.
+    pub fn load_from(path: &Path) -> Result<Self, IoError> {
+        let mut text = String::new();
+        match File::open(path) {
+            Ok(mut f) => try!(f.read_to_string(&mut text)),
+            Err(_) => return Ok(State::default()),
+        };
+
+        let mut values = BTreeMap::new();
+        for line in text.lines() {
+            if let Some(i) = line.find('=') {
+                values.insert(line[..i].trim().to_string(), line[i + 1..].trim().to_string());
+            }
+        }
+        Ok(State {
+            values: values,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), IoError> {
+        match state_path() {
+            Some(path) => self.save_to(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the state to `path`, creating its directory.
+    pub fn save_to(&self, path: &Path) -> Result<(), IoError> {
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut file = try!(File::create(path));
+        for (key, value) in &self.values {
+            try!(writeln!(file, "{} = {}", key, value));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v as &str)
+    }
+
+    pub fn set<V: ToString>(&mut self, key: String, value: V) {
+        self.values.insert(key, value.to_string());
+    }
+}
+
+pub fn state_path() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state").map(|d| d.join("syshooks").join("state"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{
+        scratch_dir,
+        write,
+    };
+
+    #[test]
+    fn saves_and_loads_values() {
+        let dir = scratch_dir("state-round-trip");
+        let path = dir.join("syshooks/state");
+
+        let mut state = State::default();
+        state.set("volume.default.Master".to_string(), 0.25);
+        state.set("volume.default.Master.muted".to_string(), true);
+        state.set("brightness.fs.intel_backlight".to_string(), 937);
+        state.save_to(&path).unwrap();
+
+        let loaded = State::load_from(&path).unwrap();
+        assert_eq!(loaded.get("volume.default.Master"), Some("0.25"));
+        assert_eq!(loaded.get("volume.default.Master.muted"), Some("true"));
+        assert_eq!(loaded.get("brightness.fs.intel_backlight"), Some("937"));
+        assert_eq!(loaded.get("brightness.xcb.eDP-1"), None);
+    }
+
+    #[test]
+    fn parses_key_value_lines() {
+        let dir = scratch_dir("state-parse");
+        let path = dir.join("state");
+        write(&path, "a = 1\n  b=two words  \nno equals sign\n\nc = x = y\n");
+
+        let state = State::load_from(&path).unwrap();
+        assert_eq!(state.get("a"), Some("1"));
+        assert_eq!(state.get("b"), Some("two words"));
+        assert_eq!(state.get("c"), Some("x = y"));
+        assert_eq!(state.get("no equals sign"), None);
+    }
+
+    #[test]
+    fn treats_a_missing_or_unreadable_file_as_empty() {
+        let dir = scratch_dir("state-unreadable");
+        assert_eq!(State::load_from(&dir.join("missing")).unwrap().values.len(), 0);
+
+        // A path below a regular file can't be opened, even by root.
+        write(&dir.join("file"), "a = 1\n");
+        assert_eq!(State::load_from(&dir.join("file/state")).unwrap().values.len(), 0);
+    }
+}
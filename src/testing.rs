@@ -0,0 +1,239 @@
+//! Helpers shared by the tests: scratch files for fabricated sysfs trees,
+//! a backlight that lives in memory, and private X servers and D-Bus
+//! daemons.
+
+extern crate dbus;
+
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    stderr,
+    BufRead,
+    BufReader,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    self,
+    Child,
+    Command,
+    Stdio,
+};
+use std::sync::mpsc::channel;
+use std::thread;
+
+use self::dbus::{
+    Connection,
+    ConnectionItem,
+    Message,
+};
+
+use manage::brightness::Brightness;
+
+/// An empty directory for the test called `name`, unique to this process
+/// and deleted when dropped.
+pub fn scratch_dir(name: &str) -> ScratchDir {
+    let dir = env::temp_dir().join(format!("syshooks-{}-{}", process::id(), name));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    ScratchDir {
+        path: dir,
+    }
+}
+
+/// A directory that is removed, with everything in it, when dropped.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// Writes `contents` to `path`, creating its parent directories.
+pub fn write(path: &Path, contents: &str) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+}
+
+#[derive(Debug)]
+pub struct NoError;
+
+impl fmt::Display for NoError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl Error for NoError {
+    fn description(&self) -> &str {
+        "no error"
+    }
+}
+
+/// A backlight that only lives in memory.
+pub struct FakeBrightness {
+    pub min: f64,
+    pub max: f64,
+    pub value: Cell<f64>,
+}
+
+impl FakeBrightness {
+    pub fn new(min: f64, max: f64, value: f64) -> Self {
+        FakeBrightness {
+            min: min,
+            max: max,
+            value: Cell::new(value),
+        }
+    }
+}
+
+impl Brightness for FakeBrightness {
+    type E = NoError;
+
+    fn max(&self) -> Result<f64, NoError> {
+        Ok(self.max)
+    }
+
+    fn min(&self) -> Result<f64, NoError> {
+        Ok(self.min)
+    }
+
+    fn current(&self) -> Result<f64, NoError> {
+        Ok(self.value.get())
+    }
+
+    fn set(&self, value: f64) -> Result<(), NoError> {
+        self.value.set(value);
+        Ok(())
+    }
+}
+
+/// Starts `command`, which prints an address on its first line of output
+/// once it is ready, or returns `None` if it isn't installed so the test
+/// can be skipped.
+fn spawn_server(command: &mut Command) -> Option<(Child, String)> {
+    let mut child = match command.stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            writeln!(stderr(), "skipping, could not start {:?}: {}", command, e).ok();
+            return None;
+        },
+    };
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    Some((child, line.trim().to_string()))
+}
+
+/// A private virtual X server, killed when dropped.
+pub struct Xvfb {
+    child: Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// Starts the server. Tests using it are `#[ignore]`d and run with
+    /// `cargo test -- --ignored` where Xvfb is installed, so this panics
+    /// rather than skipping.
+    pub fn start() -> Self {
+        let mut command = Command::new("Xvfb");
+        command.args(&["-displayfd", "1", "-nolisten", "tcp", "-screen", "0", "640x480x24"]);
+        let (child, number) = spawn_server(&mut command).expect("Xvfb is not installed");
+        Xvfb {
+            child: child,
+            display: format!(":{}", number),
+        }
+    }
+
+    /// The display name, e.g. `:1`.
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// A private D-Bus daemon, killed when dropped.
+pub struct DbusDaemon {
+    child: Child,
+    address: String,
+}
+
+impl DbusDaemon {
+    pub fn start() -> Option<Self> {
+        let mut command = Command::new("dbus-daemon");
+        command.args(&["--session", "--nofork", "--print-address=1"]);
+        spawn_server(&mut command).map(|(child, address)| DbusDaemon {
+            child: child,
+            address: address,
+        })
+    }
+
+    /// The bus address, e.g. `unix:path=/tmp/dbus-...`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Owns `name` on the bus and answers every method call on `paths`
+    /// with `reply`, from another thread, until the daemon goes away.
+    /// Returns once the name is owned.
+    pub fn serve<F>(&self, name: &str, paths: &[&str], reply: F)
+        where F: Fn(&Message) -> Message + Send + 'static
+    {
+        let (address, name) = (self.address.clone(), name.to_string());
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        let (ready, wait) = channel();
+        thread::spawn(move || {
+            let connection = Connection::open_private(&address).unwrap();
+            connection.register().unwrap();
+            for path in &paths {
+                connection.register_object_path(path).unwrap();
+            }
+            connection.register_name(&name, 0).unwrap();
+            ready.send(()).unwrap();
+
+            for item in connection.iter(100) {
+                if let ConnectionItem::MethodCall(call) = item {
+                    connection.send(reply(&call)).ok();
+                }
+            }
+        });
+        wait.recv().unwrap();
+    }
+}
+
+impl Drop for DbusDaemon {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}